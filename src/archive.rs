@@ -0,0 +1,204 @@
+use crate::dev_accounts::DevAccounts;
+use crate::journal::Operation;
+use crate::simulacrum::{highest_checkpoint, Simulacrum};
+use crate::state_dir::{PersistedChain, StateMetadata};
+use anyhow::{anyhow, bail, Context};
+use iota_types::digests::CheckpointDigest;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"IOTASIMA";
+/// Bumped whenever the encoding of the archive or the journal changes.
+pub const ARCHIVE_VERSION: u32 = 2;
+
+/// A chain stored in a single file, to be shared between machines.
+///
+/// Genesis and the committee are stored as the network config they were created from, so
+/// loading does not depend on the simulator generating the same genesis. Simulacrum can
+/// only start from genesis, so the objects and checkpoints since are rebuilt by replaying
+/// the journal, and the digests of all checkpoints verify that it rebuilt the same chain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Archive {
+    /// Name and version of the simulator that wrote the archive
+    pub created_by: String,
+    pub seed: u64,
+    /// Genesis and the keys of the committee, as a `network.yaml`
+    pub network_config: String,
+    pub dev_mnemonic: Option<String>,
+    pub dev_account_count: u32,
+    pub journal: Vec<Operation>,
+    /// By sequence number; the first one is the chain identifier
+    pub checkpoint_digests: Vec<CheckpointDigest>,
+}
+
+impl Archive {
+    pub fn from_simulacrum(sim: &Simulacrum, dev_accounts: &DevAccounts) -> anyhow::Result<Self> {
+        Ok(Self {
+            created_by: concat!("iota-l1-simulator ", env!("CARGO_PKG_VERSION")).to_string(),
+            seed: sim.seed(),
+            network_config: serde_yaml::to_string(sim.network_config().as_ref())?,
+            dev_mnemonic: dev_accounts.mnemonic.clone(),
+            dev_account_count: dev_accounts.accounts.len() as u32,
+            journal: sim.journal().to_vec(),
+            checkpoint_digests: checkpoint_digests(sim),
+        })
+    }
+
+    pub fn chain(&self) -> anyhow::Result<PersistedChain> {
+        let network_config =
+            serde_yaml::from_str(&self.network_config).context("invalid network config")?;
+        Ok(PersistedChain {
+            metadata: StateMetadata {
+                seed: self.seed,
+                dev_mnemonic: self.dev_mnemonic.clone(),
+                dev_account_count: self.dev_account_count,
+            },
+            network_config: Arc::new(network_config),
+            journal: self.journal.clone(),
+        })
+    }
+
+    /// The magic bytes and the little endian version, followed by the BCS encoded archive.
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        bytes.extend(bcs::to_bytes(self)?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            bail!("not a simulator archive");
+        };
+        let version = rest
+            .get(..4)
+            .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+            .ok_or_else(|| anyhow!("truncated archive"))?;
+        if version != ARCHIVE_VERSION {
+            bail!("archive version {version} is not supported, expected {ARCHIVE_VERSION}");
+        }
+
+        bcs::from_bytes(&rest[4..]).context("corrupt archive")
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_bytes()?)
+            .with_context(|| format!("failed to write archive {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read archive {}", path.display()))?;
+        Self::from_bytes(&bytes).with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    /// Checks that `sim`, rebuilt from this archive, produced the stored checkpoints.
+    pub fn verify(&self, sim: &Simulacrum) -> anyhow::Result<()> {
+        self.verify_digests(&checkpoint_digests(sim))
+    }
+
+    fn verify_digests(&self, rebuilt: &[CheckpointDigest]) -> anyhow::Result<()> {
+        if rebuilt.len() != self.checkpoint_digests.len() {
+            bail!(
+                "the archive holds {} checkpoints, but rebuilding it produced {}",
+                self.checkpoint_digests.len(),
+                rebuilt.len()
+            );
+        }
+
+        for (sequence_number, (stored, rebuilt)) in
+            self.checkpoint_digests.iter().zip(rebuilt).enumerate()
+        {
+            if stored != rebuilt {
+                bail!(
+                    "checkpoint {sequence_number} of the archive has digest {stored}, but \
+                     rebuilding it produced {rebuilt}; the archive is corrupt or was written \
+                     by an incompatible simulator ({})",
+                    self.created_by
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// By sequence number.
+fn checkpoint_digests(sim: &Simulacrum) -> Vec<CheckpointDigest> {
+    match highest_checkpoint(sim) {
+        Some(highest) => (0..=highest)
+            .filter_map(|sequence_number| {
+                sim.store()
+                    .get_checkpoint_by_sequence_number(sequence_number)
+                    .map(|checkpoint| *checkpoint.digest())
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive() -> Archive {
+        Archive {
+            created_by: "iota-l1-simulator test".to_string(),
+            seed: 7,
+            network_config: String::new(),
+            dev_mnemonic: None,
+            dev_account_count: 2,
+            journal: vec![Operation::CreateCheckpoint],
+            checkpoint_digests: vec![
+                CheckpointDigest::new([1; 32]),
+                CheckpointDigest::new([2; 32]),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let loaded = Archive::from_bytes(&archive().to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.seed, 7);
+        assert_eq!(loaded.dev_account_count, 2);
+        assert_eq!(loaded.journal.len(), 1);
+        assert_eq!(loaded.checkpoint_digests, archive().checkpoint_digests);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let mut bytes = archive().to_bytes().unwrap();
+        bytes[0] = b'X';
+        let err = Archive::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err.to_string(), "not a simulator archive");
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = archive().to_bytes().unwrap();
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&1u32.to_le_bytes());
+        let err = Archive::from_bytes(&bytes).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("archive version 1 is not supported"));
+    }
+
+    #[test]
+    fn differing_checkpoints_fail_verification() {
+        let archive = archive();
+        assert!(archive.verify_digests(&archive.checkpoint_digests).is_ok());
+
+        let err = archive
+            .verify_digests(&[
+                CheckpointDigest::new([1; 32]),
+                CheckpointDigest::new([3; 32]),
+            ])
+            .unwrap_err();
+        assert!(err.to_string().starts_with("checkpoint 1 of the archive"));
+
+        let err = archive
+            .verify_digests(&archive.checkpoint_digests[..1])
+            .unwrap_err();
+        assert!(err.to_string().contains("rebuilding it produced 1"));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::simulacrum::Simulacrum;
+use iota_types::messages_checkpoint::VerifiedCheckpoint;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// When checkpoints are created for transactions executed through the REST API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BlockProductionMode {
+    /// Create a checkpoint after every executed transaction
+    Automine,
+    /// Create a checkpoint on a fixed wall-clock interval
+    Interval { interval_ms: u64 },
+    /// Create a checkpoint once this many executed transactions are pending
+    Threshold { pending_transactions: u64 },
+    /// Only create checkpoints through the control API
+    #[default]
+    Manual,
+}
+
+impl BlockProductionMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Interval { interval_ms: 0 } => Err("interval_ms must be positive".to_string()),
+            Self::Threshold {
+                pending_transactions: 0,
+            } => Err("pending_transactions must be positive".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for BlockProductionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, value) = match s.split_once(':') {
+            Some((mode, value)) => (mode, Some(value)),
+            None => (s, None),
+        };
+        let parse_value = || {
+            value
+                .ok_or_else(|| format!("block production mode `{mode}` requires a value"))?
+                .parse::<u64>()
+                .map_err(|err| format!("invalid value for block production mode `{mode}`: {err}"))
+        };
+
+        let parsed = match mode {
+            "automine" => Self::Automine,
+            "manual" => Self::Manual,
+            "interval" => Self::Interval {
+                interval_ms: parse_value()?,
+            },
+            "threshold" => Self::Threshold {
+                pending_transactions: parse_value()?,
+            },
+            _ => return Err(format!("unknown block production mode `{s}`")),
+        };
+        parsed.validate()?;
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for BlockProductionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Automine => write!(f, "automine"),
+            Self::Interval { interval_ms } => write!(f, "interval:{interval_ms}"),
+            Self::Threshold {
+                pending_transactions,
+            } => write!(f, "threshold:{pending_transactions}"),
+            Self::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+/// Creates checkpoints according to the current [`BlockProductionMode`], which can be
+/// switched at runtime. The pending transactions are counted by [`Simulacrum`], so that
+/// checkpoints created in any other way reset the count as well.
+pub struct BlockProducer {
+    mode: Mutex<BlockProductionMode>,
+    mode_changed: Notify,
+}
+
+impl BlockProducer {
+    pub fn new(mode: BlockProductionMode) -> Self {
+        Self {
+            mode: Mutex::new(mode),
+            mode_changed: Notify::new(),
+        }
+    }
+
+    pub fn mode(&self) -> BlockProductionMode {
+        self.mode.lock().unwrap().clone()
+    }
+
+    pub fn set_mode(&self, mode: BlockProductionMode) {
+        *self.mode.lock().unwrap() = mode;
+        self.mode_changed.notify_one();
+    }
+
+    /// Must be called with the write lock still held after a transaction was executed.
+    pub fn on_transaction_executed(&self, sim: &mut Simulacrum) {
+        let checkpoint_due = match self.mode() {
+            BlockProductionMode::Automine => true,
+            BlockProductionMode::Threshold {
+                pending_transactions,
+            } => sim.pending_transactions() >= pending_transactions,
+            BlockProductionMode::Interval { .. } | BlockProductionMode::Manual => false,
+        };
+
+        if checkpoint_due {
+            self.create_checkpoint(sim);
+        }
+    }
+
+    pub fn create_checkpoint(&self, sim: &mut Simulacrum) -> VerifiedCheckpoint {
+        sim.create_checkpoint()
+    }
+}
+
+/// Creates a checkpoint every interval while the interval mode is active.
+pub async fn run_block_producer(
+    producer: Arc<BlockProducer>,
+    sim: Arc<RwLock<Simulacrum>>,
+    cancellation_token: CancellationToken,
+) {
+    loop {
+        let interval = match producer.mode() {
+            BlockProductionMode::Interval { interval_ms } => {
+                Some(Duration::from_millis(interval_ms))
+            }
+            _ => None,
+        };
+
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return,
+            // Re-evaluate the interval whenever the mode is switched
+            _ = producer.mode_changed.notified() => {}
+            _ = sleep_or_wait_forever(interval) => {
+                producer.create_checkpoint(&mut sim.write().unwrap());
+            }
+        }
+    }
+}
+
+async fn sleep_or_wait_forever(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_round_trips_through_its_command_line_form() {
+        for mode in [
+            BlockProductionMode::Automine,
+            BlockProductionMode::Manual,
+            BlockProductionMode::Interval { interval_ms: 250 },
+            BlockProductionMode::Threshold {
+                pending_transactions: 10,
+            },
+        ] {
+            assert_eq!(mode.to_string().parse::<BlockProductionMode>(), Ok(mode));
+        }
+    }
+
+    #[test]
+    fn invalid_modes_are_rejected() {
+        for invalid in [
+            "",
+            "sometimes",
+            "interval",
+            "interval:",
+            "interval:soon",
+            "interval:0",
+            "threshold:-1",
+            "threshold:0",
+        ] {
+            assert!(
+                invalid.parse::<BlockProductionMode>().is_err(),
+                "`{invalid}` was accepted"
+            );
+        }
+    }
+}
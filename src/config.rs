@@ -0,0 +1,825 @@
+use crate::block_production::BlockProductionMode;
+use crate::consts::{
+    get_binding_ip, get_client_url, DEFAULT_CONTROL_PORT, DEFAULT_DB_NAME,
+    DEFAULT_DEV_ACCOUNT_BALANCE, DEFAULT_FAUCET_ADVANCE_CLOCK_MS, DEFAULT_FAUCET_AMOUNT,
+    DEFAULT_FAUCET_MAX_AMOUNT, DEFAULT_FAUCET_PORT, DEFAULT_FAUCET_RATE_LIMIT_WINDOW_SECS,
+    DEFAULT_INDEXER_PORT, DEFAULT_POSTGRES_URL, DEFAULT_RPC_PORT,
+};
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use iota_types::base_types::IotaAddress;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "iota-l1-simulator",
+    version,
+    about = "A Simulacrum based L1 simulator"
+)]
+pub struct Cli {
+    /// Optional TOML config file. Values from the environment and the command line take precedence.
+    #[arg(long, short, env = "IOTA_SIM_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub overrides: ConfigOverrides,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, Default)]
+pub enum Command {
+    /// Start the simulator (default)
+    #[default]
+    Start,
+    /// Print the effective configuration as TOML and exit
+    Config,
+    /// Build the chain without starting any service and write it to an archive file
+    Dump {
+        /// File the archive is written to
+        output: PathBuf,
+    },
+}
+
+/// Every config value that can be overridden from the command line or the environment.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    #[arg(long, env = "IOTA_SIM_BIND_ADDRESS", global = true)]
+    pub bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "IOTA_SIM_INDEXER_PORT", global = true)]
+    pub indexer_port: Option<u16>,
+
+    #[arg(long, env = "IOTA_SIM_INDEXER_BIND_ADDRESS", global = true)]
+    pub indexer_bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "IOTA_SIM_INDEXER_PUBLIC_URL", global = true)]
+    pub indexer_public_url: Option<String>,
+
+    #[arg(long, env = "IOTA_SIM_RPC_PORT", global = true)]
+    pub rpc_port: Option<u16>,
+
+    #[arg(long, env = "IOTA_SIM_RPC_BIND_ADDRESS", global = true)]
+    pub rpc_bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "IOTA_SIM_RPC_PUBLIC_URL", global = true)]
+    pub rpc_public_url: Option<String>,
+
+    #[arg(long, env = "IOTA_SIM_FAUCET_PORT", global = true)]
+    pub faucet_port: Option<u16>,
+
+    #[arg(long, env = "IOTA_SIM_FAUCET_BIND_ADDRESS", global = true)]
+    pub faucet_bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "IOTA_SIM_FAUCET_PUBLIC_URL", global = true)]
+    pub faucet_public_url: Option<String>,
+
+    /// NANOS sent per faucet request unless the request asks for a different amount
+    #[arg(long, env = "IOTA_SIM_FAUCET_AMOUNT", global = true)]
+    pub faucet_amount: Option<u64>,
+
+    /// Largest amount in NANOS a single faucet request may ask for
+    #[arg(long, env = "IOTA_SIM_FAUCET_MAX_AMOUNT", global = true)]
+    pub faucet_max_amount: Option<u64>,
+
+    /// Whether the faucet creates a checkpoint after sending gas, never does, or leaves it to
+    /// the block production mode
+    #[arg(long, value_enum, env = "IOTA_SIM_FAUCET_CHECKPOINT", global = true)]
+    pub faucet_checkpoint: Option<FaucetCheckpoint>,
+
+    /// Milliseconds the clock is advanced after sending gas, 0 to leave the clock alone
+    #[arg(long, env = "IOTA_SIM_FAUCET_ADVANCE_CLOCK_MS", global = true)]
+    pub faucet_advance_clock_ms: Option<u64>,
+
+    /// Faucet requests allowed per recipient address within the rate limit window
+    #[arg(long, env = "IOTA_SIM_FAUCET_REQUESTS_PER_ADDRESS", global = true)]
+    pub faucet_requests_per_address: Option<u32>,
+
+    /// Faucet requests allowed per client IP within the rate limit window
+    #[arg(long, env = "IOTA_SIM_FAUCET_REQUESTS_PER_IP", global = true)]
+    pub faucet_requests_per_ip: Option<u32>,
+
+    /// Length of the faucet rate limit window in seconds
+    #[arg(long, env = "IOTA_SIM_FAUCET_RATE_LIMIT_WINDOW_SECS", global = true)]
+    pub faucet_rate_limit_window_secs: Option<u64>,
+
+    /// NANOS a recipient address may receive from the faucet per day
+    #[arg(long, env = "IOTA_SIM_FAUCET_DAILY_AMOUNT_PER_ADDRESS", global = true)]
+    pub faucet_daily_amount_per_address: Option<u64>,
+
+    /// NANOS that may be requested from the faucet per client IP per day
+    #[arg(long, env = "IOTA_SIM_FAUCET_DAILY_AMOUNT_PER_IP", global = true)]
+    pub faucet_daily_amount_per_ip: Option<u64>,
+
+    #[arg(long, env = "IOTA_SIM_CONTROL_PORT", global = true)]
+    pub control_port: Option<u16>,
+
+    #[arg(long, env = "IOTA_SIM_CONTROL_BIND_ADDRESS", global = true)]
+    pub control_bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "IOTA_SIM_CONTROL_PUBLIC_URL", global = true)]
+    pub control_public_url: Option<String>,
+
+    /// Number of prefunded dev accounts derived from the dev mnemonic
+    #[arg(long, env = "IOTA_SIM_DEV_ACCOUNTS", global = true)]
+    pub dev_accounts: Option<u32>,
+
+    /// NANOS every dev account is funded with
+    #[arg(long, env = "IOTA_SIM_DEV_ACCOUNT_BALANCE", global = true)]
+    pub dev_account_balance: Option<u64>,
+
+    /// BIP-39 mnemonic the dev accounts are derived from. Generated if not given
+    #[arg(long, env = "IOTA_SIM_DEV_MNEMONIC", global = true)]
+    pub dev_mnemonic: Option<String>,
+
+    /// IOTA CLI keystore file the dev account keys are written to
+    #[arg(long, env = "IOTA_SIM_DEV_KEYSTORE", global = true)]
+    pub dev_keystore: Option<PathBuf>,
+
+    /// Move package directories to build and publish from the first dev account at startup
+    #[arg(long, env = "IOTA_SIM_PUBLISH", value_delimiter = ',', global = true)]
+    pub publish: Option<Vec<PathBuf>>,
+
+    /// Directory of checkpoint files of a real network to fork the chain from
+    #[arg(long, env = "IOTA_SIM_FORK_CHECKPOINTS", global = true)]
+    pub fork_checkpoints: Option<PathBuf>,
+
+    /// BCS encoded object snapshot of a real network to fork the chain from
+    #[arg(long, env = "IOTA_SIM_FORK_OBJECTS", global = true)]
+    pub fork_objects: Option<PathBuf>,
+
+    /// Whether the indexer uses the Postgres server at `--db-url`, a throwaway server
+    /// started by the simulator, or is disabled
+    #[arg(long, value_enum, env = "IOTA_SIM_DB_MODE", global = true)]
+    pub db_mode: Option<DatabaseMode>,
+
+    /// Postgres server URL, without the database name
+    #[arg(long, env = "IOTA_SIM_DB_URL", global = true)]
+    pub db_url: Option<String>,
+
+    /// Name of the database the indexer writes to
+    #[arg(long, env = "IOTA_SIM_DB_NAME", global = true)]
+    pub db_name: Option<String>,
+
+    /// Directory holding `initdb` and `pg_ctl` for the managed database, if not in `PATH`
+    #[arg(long, env = "IOTA_SIM_POSTGRES_BIN_DIR", global = true)]
+    pub postgres_bin_dir: Option<PathBuf>,
+
+    /// Seed for genesis and the seeding transactions. Runs with the same seed produce
+    /// identical checkpoints and digests
+    #[arg(long, env = "IOTA_SIM_SEED", global = true)]
+    pub seed: Option<u64>,
+
+    /// YAML or JSON scenario that bootstraps the chain, replacing the seeding transfers
+    #[arg(long, env = "IOTA_SIM_SCENARIO", global = true)]
+    pub scenario: Option<PathBuf>,
+
+    /// Number of seeding transfers per epoch, e.g. `15,10,5` for three epochs
+    #[arg(
+        long,
+        env = "IOTA_SIM_SEED_TRANSACTIONS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub seed_transactions: Option<Vec<u32>>,
+
+    /// Number of checkpoints created after the seeding transfers of each epoch
+    #[arg(long, env = "IOTA_SIM_SEED_CHECKPOINTS", global = true)]
+    pub seed_checkpoints: Option<u32>,
+
+    /// When to create checkpoints: `automine`, `manual`, `interval:<ms>` or `threshold:<transactions>`
+    #[arg(long, env = "IOTA_SIM_BLOCK_PRODUCTION", global = true)]
+    pub block_production: Option<BlockProductionMode>,
+
+    /// Let the OS pick a free port for every service, ignoring the configured ports
+    #[arg(long, env = "IOTA_SIM_EPHEMERAL_PORTS", global = true)]
+    pub ephemeral_ports: bool,
+
+    /// File the service endpoints are written to as JSON once the simulator is ready
+    #[arg(long, env = "IOTA_SIM_READY_FILE", global = true)]
+    pub ready_file: Option<PathBuf>,
+
+    /// Directory the chain is persisted to and resumed from on the next start
+    #[arg(long, env = "IOTA_SIM_STATE_DIR", global = true)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Archive file written by `dump` or the control API to boot the chain from
+    #[arg(long, env = "IOTA_SIM_LOAD_ARCHIVE", global = true)]
+    pub load_archive: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatorConfig {
+    pub bind_address: IpAddr,
+    pub ephemeral_ports: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ready_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_archive: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<PathBuf>,
+    #[serde(deserialize_with = "indexer_service")]
+    pub indexer: ServiceConfig,
+    #[serde(deserialize_with = "rpc_service")]
+    pub rpc: ServiceConfig,
+    #[serde(deserialize_with = "faucet_service")]
+    pub faucet: ServiceConfig,
+    #[serde(deserialize_with = "control_service")]
+    pub control: ServiceConfig,
+    pub faucet_policy: FaucetPolicy,
+    pub dev_accounts: DevAccountsConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub publish: Vec<PublishConfig>,
+    #[serde(skip_serializing_if = "ForkConfig::is_disabled")]
+    pub fork: ForkConfig,
+    pub database: DatabaseConfig,
+    pub seeding: SeedingConfig,
+    pub block_production: BlockProductionMode,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ServiceConfig {
+    pub port: u16,
+    /// Interface the service listens on. Falls back to the global `bind_address`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<IpAddr>,
+    /// URL under which other services and clients reach this service, e.g.
+    /// `http://simulator:30001` inside a docker-compose network.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+}
+
+/// A service table of the config file, whose missing keys take the service's defaults.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceTable {
+    port: Option<u16>,
+    bind_address: Option<IpAddr>,
+    public_url: Option<String>,
+}
+
+fn service_table<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    default_port: u16,
+) -> Result<ServiceConfig, D::Error> {
+    let table = ServiceTable::deserialize(deserializer)?;
+    let mut service = ServiceConfig::new(default_port);
+    service.apply_overrides(table.port, table.bind_address, table.public_url);
+    Ok(service)
+}
+
+fn indexer_service<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ServiceConfig, D::Error> {
+    service_table(deserializer, DEFAULT_INDEXER_PORT)
+}
+
+fn rpc_service<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ServiceConfig, D::Error> {
+    service_table(deserializer, DEFAULT_RPC_PORT)
+}
+
+fn faucet_service<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ServiceConfig, D::Error> {
+    service_table(deserializer, DEFAULT_FAUCET_PORT)
+}
+
+fn control_service<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ServiceConfig, D::Error> {
+    service_table(deserializer, DEFAULT_CONTROL_PORT)
+}
+
+/// The advertised URLs of all services, as published to stdout and the ready-file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Endpoints {
+    /// `None` if the indexer is disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexer: Option<String>,
+    pub rpc: String,
+    pub faucet: String,
+    pub control: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub mode: DatabaseMode,
+    /// Server URL in the external mode
+    pub url: String,
+    pub name: String,
+    /// Directory holding `initdb` and `pg_ctl` in the managed mode. They are looked up in
+    /// `PATH` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postgres_bin_dir: Option<PathBuf>,
+}
+
+/// Where the indexer keeps its data.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DatabaseMode {
+    /// Use the Postgres server at `url`
+    #[default]
+    External,
+    /// Start a throwaway Postgres server in a temporary directory and remove it on exit
+    Managed,
+    /// Run without indexer, serving only the REST API, the faucet and the control API
+    Disabled,
+}
+
+/// What the faucet hands out per request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaucetPolicy {
+    /// NANOS sent when the request does not ask for an amount
+    pub amount: u64,
+    /// Upper limit for amounts requested with `?amount=`
+    pub max_amount: u64,
+    pub checkpoint: FaucetCheckpoint,
+    pub advance_clock_ms: u64,
+    pub limits: FaucetLimits,
+}
+
+/// Optional limits on faucet requests. Exceeding one is answered with
+/// `429 Too Many Requests`, as the real faucet does.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaucetLimits {
+    /// Requests per recipient address within `window_secs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_per_address: Option<u32>,
+    /// Requests per client IP within `window_secs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_per_ip: Option<u32>,
+    pub window_secs: u64,
+    /// NANOS a recipient address may receive per day
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_amount_per_address: Option<u64>,
+    /// NANOS that may be requested from a client IP per day
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_amount_per_ip: Option<u64>,
+}
+
+/// Whether sent gas is checkpointed right away.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum FaucetCheckpoint {
+    /// Create a checkpoint after every request, or every batch of `/v1/gas` requests
+    #[default]
+    Always,
+    /// Treat gas transfers like any other executed transaction
+    BlockProduction,
+    /// Never create a checkpoint
+    Never,
+}
+
+/// Prefunded accounts whose keys are managed by the simulator.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevAccountsConfig {
+    pub count: u32,
+    /// NANOS every account is funded with
+    pub balance: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+    /// IOTA CLI keystore file the keys are written to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keystore: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig {
+    pub path: PathBuf,
+    /// Values for named addresses that `Move.toml` leaves unassigned
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub named_addresses: BTreeMap<String, IotaAddress>,
+}
+
+/// Objects exported from a real network, which the chain starts with on top of its
+/// local genesis.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForkConfig {
+    /// Directory of `<sequence number>.chk` files in the format of the data ingestion
+    /// directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoints: Option<PathBuf>,
+    /// File with a BCS encoded `Vec<Object>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub objects: Option<PathBuf>,
+}
+
+impl ForkConfig {
+    pub fn is_disabled(&self) -> bool {
+        self.checkpoints.is_none() && self.objects.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeedingConfig {
+    /// One entry per epoch; the epoch is advanced between entries.
+    pub transactions_per_epoch: Vec<u32>,
+    pub checkpoints_per_epoch: u32,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            ephemeral_ports: false,
+            ready_file: None,
+            state_dir: None,
+            load_archive: None,
+            seed: None,
+            scenario: None,
+            indexer: ServiceConfig::new(DEFAULT_INDEXER_PORT),
+            rpc: ServiceConfig::new(DEFAULT_RPC_PORT),
+            faucet: ServiceConfig::new(DEFAULT_FAUCET_PORT),
+            control: ServiceConfig::new(DEFAULT_CONTROL_PORT),
+            faucet_policy: FaucetPolicy::default(),
+            dev_accounts: DevAccountsConfig::default(),
+            publish: Vec::new(),
+            fork: ForkConfig::default(),
+            database: DatabaseConfig::default(),
+            seeding: SeedingConfig::default(),
+            block_production: BlockProductionMode::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            mode: DatabaseMode::default(),
+            url: DEFAULT_POSTGRES_URL.to_string(),
+            name: DEFAULT_DB_NAME.to_string(),
+            postgres_bin_dir: None,
+        }
+    }
+}
+
+impl Default for FaucetPolicy {
+    fn default() -> Self {
+        Self {
+            amount: DEFAULT_FAUCET_AMOUNT,
+            max_amount: DEFAULT_FAUCET_MAX_AMOUNT,
+            checkpoint: FaucetCheckpoint::default(),
+            advance_clock_ms: DEFAULT_FAUCET_ADVANCE_CLOCK_MS,
+            limits: FaucetLimits::default(),
+        }
+    }
+}
+
+impl Default for FaucetLimits {
+    fn default() -> Self {
+        Self {
+            requests_per_address: None,
+            requests_per_ip: None,
+            window_secs: DEFAULT_FAUCET_RATE_LIMIT_WINDOW_SECS,
+            daily_amount_per_address: None,
+            daily_amount_per_ip: None,
+        }
+    }
+}
+
+impl FaucetPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.amount == 0 {
+            return Err("faucet amount must be positive".to_string());
+        }
+        if self.amount > self.max_amount {
+            return Err(format!(
+                "faucet amount {} exceeds the maximum of {}",
+                self.amount, self.max_amount
+            ));
+        }
+        if self.limits.window_secs == 0 {
+            return Err("faucet rate limit window must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for DevAccountsConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            balance: DEFAULT_DEV_ACCOUNT_BALANCE,
+            mnemonic: None,
+            keystore: None,
+        }
+    }
+}
+
+impl Default for SeedingConfig {
+    fn default() -> Self {
+        Self {
+            transactions_per_epoch: vec![15, 10, 5],
+            checkpoints_per_epoch: 300,
+        }
+    }
+}
+
+impl ServiceConfig {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            bind_address: None,
+            public_url: None,
+        }
+    }
+
+    fn apply_overrides(
+        &mut self,
+        port: Option<u16>,
+        bind_address: Option<IpAddr>,
+        public_url: Option<String>,
+    ) {
+        if let Some(v) = port {
+            self.port = v;
+        }
+        if let Some(v) = bind_address {
+            self.bind_address = Some(v);
+        }
+        if let Some(v) = public_url {
+            self.public_url = Some(v);
+        }
+    }
+}
+
+impl SimulatorConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.block_production
+            .validate()
+            .map_err(anyhow::Error::msg)?;
+        self.faucet_policy.validate().map_err(anyhow::Error::msg)?;
+        if !self.publish.is_empty() && self.dev_accounts.count == 0 {
+            anyhow::bail!("publishing packages requires at least one dev account (--dev-accounts)");
+        }
+        Ok(())
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        let o = overrides.clone();
+
+        if let Some(v) = o.bind_address {
+            self.bind_address = v;
+        }
+        self.indexer
+            .apply_overrides(o.indexer_port, o.indexer_bind_address, o.indexer_public_url);
+        self.rpc
+            .apply_overrides(o.rpc_port, o.rpc_bind_address, o.rpc_public_url);
+        self.faucet
+            .apply_overrides(o.faucet_port, o.faucet_bind_address, o.faucet_public_url);
+        self.control
+            .apply_overrides(o.control_port, o.control_bind_address, o.control_public_url);
+        if let Some(v) = o.faucet_amount {
+            self.faucet_policy.amount = v;
+        }
+        if let Some(v) = o.faucet_max_amount {
+            self.faucet_policy.max_amount = v;
+        }
+        if let Some(v) = o.faucet_checkpoint {
+            self.faucet_policy.checkpoint = v;
+        }
+        if let Some(v) = o.faucet_advance_clock_ms {
+            self.faucet_policy.advance_clock_ms = v;
+        }
+        let limits = &mut self.faucet_policy.limits;
+        if let Some(v) = o.faucet_requests_per_address {
+            limits.requests_per_address = Some(v);
+        }
+        if let Some(v) = o.faucet_requests_per_ip {
+            limits.requests_per_ip = Some(v);
+        }
+        if let Some(v) = o.faucet_rate_limit_window_secs {
+            limits.window_secs = v;
+        }
+        if let Some(v) = o.faucet_daily_amount_per_address {
+            limits.daily_amount_per_address = Some(v);
+        }
+        if let Some(v) = o.faucet_daily_amount_per_ip {
+            limits.daily_amount_per_ip = Some(v);
+        }
+        if let Some(v) = o.dev_accounts {
+            self.dev_accounts.count = v;
+        }
+        if let Some(v) = o.dev_account_balance {
+            self.dev_accounts.balance = v;
+        }
+        if let Some(v) = o.dev_mnemonic {
+            self.dev_accounts.mnemonic = Some(v);
+        }
+        if let Some(v) = o.dev_keystore {
+            self.dev_accounts.keystore = Some(v);
+        }
+        if let Some(v) = o.publish {
+            self.publish.extend(v.into_iter().map(|path| PublishConfig {
+                path,
+                named_addresses: BTreeMap::new(),
+            }));
+        }
+        if let Some(v) = o.fork_checkpoints {
+            self.fork.checkpoints = Some(v);
+        }
+        if let Some(v) = o.fork_objects {
+            self.fork.objects = Some(v);
+        }
+        if let Some(v) = o.db_mode {
+            self.database.mode = v;
+        }
+        if let Some(v) = o.db_url {
+            self.database.url = v;
+        }
+        if let Some(v) = o.db_name {
+            self.database.name = v;
+        }
+        if let Some(v) = o.postgres_bin_dir {
+            self.database.postgres_bin_dir = Some(v);
+        }
+        if let Some(v) = o.seed {
+            self.seed = Some(v);
+        }
+        if let Some(v) = o.scenario {
+            self.scenario = Some(v);
+        }
+        if let Some(v) = o.seed_transactions {
+            self.seeding.transactions_per_epoch = v;
+        }
+        if let Some(v) = o.seed_checkpoints {
+            self.seeding.checkpoints_per_epoch = v;
+        }
+        if let Some(v) = o.block_production {
+            self.block_production = v;
+        }
+        if o.ephemeral_ports {
+            self.ephemeral_ports = true;
+        }
+        if let Some(v) = o.ready_file {
+            self.ready_file = Some(v);
+        }
+        if let Some(v) = o.state_dir {
+            self.state_dir = Some(v);
+        }
+        if let Some(v) = o.load_archive {
+            self.load_archive = Some(v);
+        }
+    }
+
+    /// Replaces the port of every service with one assigned by the OS.
+    ///
+    /// The faucet and the control API are bound to port 0 when they start. The REST
+    /// service and the indexer reader bind their sockets themselves, so a free port is
+    /// looked up for them by binding port 0 and releasing it again. Another process
+    /// could take it before they bind it, so the start is then retried on other ports.
+    pub fn assign_ephemeral_ports(&mut self) -> std::io::Result<()> {
+        for service in [&mut self.indexer, &mut self.rpc] {
+            let bind_address = service.bind_address.unwrap_or(self.bind_address);
+            let listener = TcpListener::bind(SocketAddr::new(bind_address, 0))?;
+            service.port = listener.local_addr()?.port();
+        }
+        self.faucet.port = 0;
+        self.control.port = 0;
+        Ok(())
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            indexer: self
+                .indexer_enabled()
+                .then(|| self.client_url(&self.indexer)),
+            rpc: self.client_url(&self.rpc),
+            faucet: self.client_url(&self.faucet),
+            control: self.client_url(&self.control),
+        }
+    }
+
+    pub fn indexer_enabled(&self) -> bool {
+        self.database.mode != DatabaseMode::Disabled
+    }
+
+    pub fn bind_address(&self, service: &ServiceConfig) -> IpAddr {
+        service.bind_address.unwrap_or(self.bind_address)
+    }
+
+    pub fn binding_ip(&self, service: &ServiceConfig) -> String {
+        get_binding_ip(self.bind_address(service), service.port)
+    }
+
+    /// The advertised URL of a service. Without an explicit `public_url` this is the
+    /// bind address, or loopback when binding to all interfaces.
+    pub fn client_url(&self, service: &ServiceConfig) -> String {
+        match &service.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => get_client_url(self.bind_address(service), service.port),
+        }
+    }
+}
+
+impl Cli {
+    /// Builds the effective config: defaults, then the config file, then env/CLI overrides.
+    pub fn load_config(&self) -> anyhow::Result<SimulatorConfig> {
+        let mut config = match &self.config {
+            Some(path) => SimulatorConfig::from_file(path)?,
+            None => SimulatorConfig::default(),
+        };
+        config.apply_overrides(&self.overrides);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_sources_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[rpc]\nport = 1001\n\n[faucet]\nport = 1002\n\n[control]\nport = 1003\n",
+        )
+        .unwrap();
+
+        // The only test setting the environment; the others do not depend on these variables
+        std::env::set_var("IOTA_SIM_FAUCET_PORT", "2002");
+        std::env::set_var("IOTA_SIM_CONTROL_PORT", "2003");
+        let cli = Cli::try_parse_from([
+            "iota-l1-simulator",
+            "--config",
+            path.to_str().unwrap(),
+            "--control-port",
+            "3003",
+        ]);
+        std::env::remove_var("IOTA_SIM_FAUCET_PORT");
+        std::env::remove_var("IOTA_SIM_CONTROL_PORT");
+        let config = cli.unwrap().load_config().unwrap();
+
+        assert_eq!(config.indexer.port, DEFAULT_INDEXER_PORT);
+        assert_eq!(config.rpc.port, 1001);
+        assert_eq!(config.faucet.port, 2002);
+        assert_eq!(config.control.port, 3003);
+    }
+
+    #[test]
+    fn partial_service_tables_keep_their_default_port() {
+        let config: SimulatorConfig =
+            toml::from_str("[rpc]\nbind_address = \"0.0.0.0\"\n\n[faucet]\nport = 1002\n").unwrap();
+
+        assert_eq!(config.rpc.port, DEFAULT_RPC_PORT);
+        assert_eq!(
+            config.rpc.bind_address,
+            Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        );
+        assert_eq!(config.faucet.port, 1002);
+        assert_eq!(config.control.port, DEFAULT_CONTROL_PORT);
+    }
+
+    #[test]
+    fn file_is_validated_after_the_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[[publish]]\npath = \"move/my_package\"\n").unwrap();
+        let config_arg = format!("--config={}", path.display());
+
+        let cli = Cli::try_parse_from(["iota-l1-simulator", &config_arg]).unwrap();
+        assert!(cli.load_config().is_err());
+
+        let cli = Cli::try_parse_from(["iota-l1-simulator", &config_arg, "--dev-accounts", "1"]);
+        assert_eq!(cli.unwrap().load_config().unwrap().dev_accounts.count, 1);
+    }
+
+    #[test]
+    fn faucet_amount_may_exceed_the_default_max_raised_by_a_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let amount = DEFAULT_FAUCET_MAX_AMOUNT + 1;
+        std::fs::write(&path, format!("[faucet_policy]\namount = {amount}\n")).unwrap();
+        let config_arg = format!("--config={}", path.display());
+
+        let cli = Cli::try_parse_from(["iota-l1-simulator", &config_arg]).unwrap();
+        assert!(cli.load_config().is_err());
+
+        let max_arg = format!("--faucet-max-amount={amount}");
+        let cli = Cli::try_parse_from(["iota-l1-simulator", &config_arg, &max_arg]);
+        assert_eq!(
+            cli.unwrap().load_config().unwrap().faucet_policy.amount,
+            amount
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::DevAccountsConfig;
+use crate::simulacrum::Simulacrum;
+use anyhow::{anyhow, Context};
+use bip32::DerivationPath;
+use bip39::{Language, Mnemonic, Seed};
+use iota_keys::key_derive::derive_key_pair_from_path;
+use iota_types::base_types::IotaAddress;
+use iota_types::crypto::{IotaKeyPair, SignatureScheme};
+use rand::rngs::StdRng;
+use rand::RngCore;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A prefunded account whose key is managed by the simulator.
+pub struct DevAccount {
+    pub address: IotaAddress,
+    pub keypair: IotaKeyPair,
+    pub derivation_path: String,
+}
+
+/// The dev accounts, derived from a single mnemonic like the accounts of an IOTA CLI
+/// wallet.
+#[derive(Default)]
+pub struct DevAccounts {
+    pub mnemonic: Option<String>,
+    pub accounts: Vec<DevAccount>,
+}
+
+/// How dev accounts are served by the control API, including their private keys.
+#[derive(Serialize, Debug, Clone)]
+pub struct DevAccountsInfo {
+    pub mnemonic: Option<String>,
+    pub accounts: Vec<DevAccountInfo>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DevAccountInfo {
+    pub address: IotaAddress,
+    /// Bech32 encoded, as accepted by `iota keytool import`
+    pub private_key: String,
+    pub derivation_path: String,
+}
+
+impl DevAccounts {
+    /// Derives the configured number of Ed25519 accounts. Without a configured mnemonic
+    /// one is generated from `rng`, so that it is reproducible with `--seed`.
+    pub fn derive(config: &DevAccountsConfig, rng: &mut StdRng) -> anyhow::Result<Self> {
+        if config.count == 0 {
+            return Ok(Self::default());
+        }
+
+        let mnemonic = match &config.mnemonic {
+            Some(phrase) => Mnemonic::from_phrase(phrase, Language::English)
+                .map_err(|err| anyhow!("invalid dev account mnemonic: {err}"))?,
+            None => {
+                let mut entropy = [0u8; 16];
+                rng.fill_bytes(&mut entropy);
+                Mnemonic::from_entropy(&entropy, Language::English)
+                    .map_err(|err| anyhow!("failed to generate a mnemonic: {err}"))?
+            }
+        };
+        let seed = Seed::new(&mnemonic, "");
+
+        let accounts = (0..config.count)
+            .map(|index| {
+                let derivation_path = format!("m/44'/4218'/0'/0'/{index}'");
+                let (address, keypair) = derive_key_pair_from_path(
+                    seed.as_bytes(),
+                    Some(DerivationPath::from_str(&derivation_path)?),
+                    &SignatureScheme::ED25519,
+                )?;
+                Ok(DevAccount {
+                    address,
+                    keypair,
+                    derivation_path,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            mnemonic: Some(mnemonic.phrase().to_string()),
+            accounts,
+        })
+    }
+
+    /// Sends every account a gas coin of `balance` NANOS.
+    pub fn fund(&self, sim: &mut Simulacrum, balance: u64) -> anyhow::Result<()> {
+        if balance == 0 {
+            return Ok(());
+        }
+        for account in &self.accounts {
+            sim.request_gas(account.address, balance)
+                .with_context(|| format!("failed to fund dev account {}", account.address))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, address: &IotaAddress) -> Option<&DevAccount> {
+        self.accounts
+            .iter()
+            .find(|account| account.address == *address)
+    }
+
+    pub fn info(&self) -> DevAccountsInfo {
+        DevAccountsInfo {
+            mnemonic: self.mnemonic.clone(),
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| DevAccountInfo {
+                    address: account.address,
+                    private_key: account
+                        .keypair
+                        .encode()
+                        .expect("Ed25519 keys can always be encoded"),
+                    derivation_path: account.derivation_path.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the keys as a keystore file of the IOTA CLI, which can be referenced from
+    /// its `client.yaml`.
+    pub fn write_keystore(&self, path: &Path) -> anyhow::Result<()> {
+        let keys: Vec<String> = self
+            .accounts
+            .iter()
+            .map(|account| account.keypair.encode_base64())
+            .collect();
+        let content = serde_json::to_string_pretty(&keys)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("failed to write keystore {}", path.display()))
+    }
+}
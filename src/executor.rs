@@ -0,0 +1,233 @@
+use crate::block_production::BlockProducer;
+use crate::simulacrum::Simulacrum;
+use iota_types::base_types::{ObjectID, ObjectRef};
+use iota_types::committee::Committee;
+use iota_types::crypto::AuthorityStrongQuorumSignInfo;
+use iota_types::digests::TransactionDigest;
+use iota_types::effects::{TransactionEffects, TransactionEffectsAPI};
+use iota_types::error::{IotaError, UserInputError};
+use iota_types::object::Object;
+use iota_types::quorum_driver_types::{
+    EffectsFinalityInfo, ExecuteTransactionRequestV1, ExecuteTransactionResponseV1,
+    FinalizedEffects, QuorumDriverError,
+};
+use iota_types::storage::{ObjectStore, ReadStore, RestStateReader};
+use iota_types::transaction::{InputObjectKind, TransactionDataAPI};
+use iota_types::transaction_executor::TransactionExecutor;
+use jsonrpsee::core::async_trait;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+pub struct SimulacrumExecuter {
+    pub inner: Arc<RwLock<Simulacrum>>,
+    pub block_producer: Arc<BlockProducer>,
+}
+
+#[async_trait]
+impl TransactionExecutor for SimulacrumExecuter {
+    async fn execute_transaction(
+        &self,
+        request: ExecuteTransactionRequestV1,
+        _client_addr: Option<SocketAddr>,
+    ) -> Result<ExecuteTransactionResponseV1, QuorumDriverError> {
+        let mut s = self.inner.write().unwrap();
+        execute_transaction(
+            &mut s,
+            &self.block_producer,
+            request,
+            ExecutionOptions::default(),
+        )
+    }
+}
+
+/// How the control API deviates from the way the REST API executes transactions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionOptions {
+    /// Checkpoint the transaction even if the block production mode leaves it pending
+    pub force_checkpoint: bool,
+    /// Skip signature verification, so that the transaction can be sent as any address
+    pub impersonate: bool,
+}
+
+/// Executes a transaction the way the REST API does, so that the control API behaves
+/// the same.
+pub fn execute_transaction(
+    s: &mut Simulacrum,
+    block_producer: &BlockProducer,
+    request: ExecuteTransactionRequestV1,
+    options: ExecutionOptions,
+) -> Result<ExecuteTransactionResponseV1, QuorumDriverError> {
+    let input_object_kinds = request
+        .transaction
+        .transaction_data()
+        .input_objects()
+        .unwrap_or_default();
+
+    // Failed Move execution still produces effects, so only transactions that could
+    // not be executed at all end up here.
+    let result = match options.impersonate {
+        true => s.execute_transaction_impersonating(request.transaction),
+        false => s.execute_transaction(request.transaction),
+    };
+    let (effects, _) = result.map_err(|err| to_quorum_driver_error(s, err))?;
+    let digest = *effects.transaction_digest();
+    block_producer.on_transaction_executed(s);
+    if options.force_checkpoint && !matches!(s.get_transaction_checkpoint(&digest), Ok(Some(_))) {
+        block_producer.create_checkpoint(s);
+    }
+
+    let events = s.store().get_transaction_events_by_tx_digest(&digest);
+    let input_objects = request
+        .include_input_objects
+        .then(|| get_input_objects(s, &effects, &input_object_kinds));
+    let output_objects = request
+        .include_output_objects
+        .then(|| get_output_objects(s, &effects));
+
+    Ok(ExecuteTransactionResponseV1 {
+        effects: FinalizedEffects {
+            finality_info: get_finality_info(s, &effects),
+            effects,
+        },
+        events,
+        input_objects,
+        output_objects,
+        auxiliary_data: None,
+    })
+}
+
+/// Checkpointed transactions report their checkpoint. Simulacrum does not certify
+/// transactions, so executed but not yet checkpointed transactions are reported as
+/// certified with an empty quorum signature.
+fn get_finality_info(s: &Simulacrum, effects: &TransactionEffects) -> EffectsFinalityInfo {
+    match s.get_transaction_checkpoint(effects.transaction_digest()) {
+        Ok(Some(sequence_number)) => {
+            EffectsFinalityInfo::Checkpointed(effects.executed_epoch(), sequence_number)
+        }
+        _ => EffectsFinalityInfo::Certified(AuthorityStrongQuorumSignInfo {
+            epoch: effects.executed_epoch(),
+            signature: Default::default(),
+            signers_map: Default::default(),
+        }),
+    }
+}
+
+/// The objects as they were read by the transaction.
+fn get_input_objects(
+    s: &Simulacrum,
+    effects: &TransactionEffects,
+    input_object_kinds: &[InputObjectKind],
+) -> Vec<Object> {
+    let modified_at_versions: BTreeMap<ObjectID, _> =
+        effects.modified_at_versions().into_iter().collect();
+
+    input_object_kinds
+        .iter()
+        .filter_map(|kind| {
+            let object = match kind {
+                InputObjectKind::ImmOrOwnedMoveObject((id, version, _)) => {
+                    s.get_object_by_key(id, *version)
+                }
+                InputObjectKind::SharedMoveObject { id, .. } => {
+                    match modified_at_versions.get(id) {
+                        Some(version) => s.get_object_by_key(id, *version),
+                        // Read-only shared objects are not modified, so their latest version is the one that was read
+                        None => s.get_object(id),
+                    }
+                }
+                InputObjectKind::MovePackage(id) => s.get_object(id),
+            };
+            object.ok().flatten()
+        })
+        .collect()
+}
+
+/// The objects created, mutated, unwrapped or published by the transaction.
+fn get_output_objects(s: &Simulacrum, effects: &TransactionEffects) -> Vec<Object> {
+    effects
+        .all_changed_objects()
+        .into_iter()
+        .filter_map(|((id, version, _), _, _)| s.get_object_by_key(&id, version).ok().flatten())
+        .collect()
+}
+
+/// Translates a transaction that could not be executed into the error the quorum driver
+/// of a real node reports. Simulacrum acts as the whole committee, so every error is
+/// attributed to all validators.
+fn to_quorum_driver_error(s: &Simulacrum, err: anyhow::Error) -> QuorumDriverError {
+    let err = match err.downcast::<IotaError>() {
+        Ok(err) => err,
+        Err(err) => {
+            return QuorumDriverError::QuorumDriverInternalError(IotaError::Unknown(
+                err.to_string(),
+            ))
+        }
+    };
+
+    let committee = current_committee(s);
+    let total_stake = committee.as_ref().map_or(0, |c| c.total_votes());
+
+    match err {
+        IotaError::InvalidSignature { .. }
+        | IotaError::SignerSignatureAbsent { .. }
+        | IotaError::SignerSignatureNumberMismatch { .. }
+        | IotaError::IncorrectSigner { .. } => QuorumDriverError::InvalidUserSignature(err),
+        IotaError::ObjectLockConflict {
+            obj_ref,
+            pending_transaction,
+        } => objects_double_used(committee.as_ref(), pending_transaction, obj_ref),
+        IotaError::UserInputError {
+            error:
+                UserInputError::ObjectVersionUnavailableForConsumption {
+                    provided_obj_ref, ..
+                },
+        } => match s.get_object(&provided_obj_ref.0) {
+            // The object was consumed by the transaction that wrote its current version
+            Ok(Some(object)) => objects_double_used(
+                committee.as_ref(),
+                object.previous_transaction,
+                provided_obj_ref,
+            ),
+            _ => non_recoverable(committee.as_ref(), total_stake, err),
+        },
+        err => non_recoverable(committee.as_ref(), total_stake, err),
+    }
+}
+
+fn current_committee(s: &Simulacrum) -> Option<Arc<Committee>> {
+    let epoch = s.get_latest_checkpoint().ok()?.data().epoch;
+    s.get_committee(epoch).ok().flatten()
+}
+
+fn objects_double_used(
+    committee: Option<&Arc<Committee>>,
+    conflicting_transaction: TransactionDigest,
+    obj_ref: ObjectRef,
+) -> QuorumDriverError {
+    let (authorities, stake) = match committee {
+        Some(committee) => (
+            committee.names().map(|name| (*name, obj_ref)).collect(),
+            committee.total_votes(),
+        ),
+        None => (Vec::new(), 0),
+    };
+
+    QuorumDriverError::ObjectsDoubleUsed {
+        conflicting_txes: BTreeMap::from([(conflicting_transaction, (authorities, stake))]),
+    }
+}
+
+fn non_recoverable(
+    committee: Option<&Arc<Committee>>,
+    total_stake: u64,
+    err: IotaError,
+) -> QuorumDriverError {
+    let authorities = committee
+        .map(|committee| committee.names().map(|name| name.concise()).collect())
+        .unwrap_or_default();
+
+    QuorumDriverError::NonRecoverableTransactionError {
+        errors: vec![(err, total_stake, authorities)],
+    }
+}
@@ -1,71 +1,472 @@
-use crate::consts::get_faucet_binding_ip;
-use axum::extract::State;
+use crate::block_production::BlockProducer;
+use crate::config::{FaucetCheckpoint, FaucetPolicy};
+use crate::faucet_limits::FaucetLimiter;
+use crate::simulacrum::Simulacrum;
+use anyhow::bail;
+use axum::extract::{ConnectInfo, Path, Query, State};
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
-use iota_faucet::{FaucetError, FaucetReceipt, FaucetRequest, FaucetResponse};
-use simulacrum::Simulacrum;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use iota_faucet::{
+    BatchFaucetResponse, BatchSendStatus, BatchSendStatusType, BatchStatusFaucetResponse, CoinInfo,
+    FaucetError, FaucetReceipt, FaucetRequest, FaucetResponse,
+};
+use iota_types::base_types::{IotaAddress, ObjectID, ObjectRef, SequenceNumber};
+use iota_types::digests::ObjectDigest;
+use iota_types::effects::TransactionEffectsAPI;
+use iota_types::messages_checkpoint::CheckpointSequenceNumber;
+use iota_types::object::Owner;
+use iota_types::storage::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// How long the batch worker waits for further `/v1/gas` requests after the first one
+const BATCH_WINDOW: Duration = Duration::from_millis(100);
+/// How long the status of a finished batch task can be queried
+const BATCH_STATUS_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Clone)]
+pub struct FaucetState {
+    pub sim: Arc<RwLock<Simulacrum>>,
+    pub block_producer: Arc<BlockProducer>,
+    pub policy: FaucetPolicy,
+    pub limiter: Arc<FaucetLimiter>,
+    batches: Arc<BatchQueue>,
+}
+
+impl FaucetState {
+    pub fn new(
+        sim: Arc<RwLock<Simulacrum>>,
+        block_producer: Arc<BlockProducer>,
+        policy: FaucetPolicy,
+        limiter: Arc<FaucetLimiter>,
+    ) -> Self {
+        Self {
+            sim,
+            block_producer,
+            policy,
+            limiter,
+            batches: Arc::default(),
+        }
+    }
+
+    /// The amount to send, if the request is within the amount and rate limits.
+    fn admit(
+        &self,
+        recipient: IotaAddress,
+        client: SocketAddr,
+        amount: Option<u64>,
+    ) -> Result<u64, (StatusCode, FaucetError)> {
+        let amount = requested_amount(&self.policy, amount)
+            .map_err(|err| (StatusCode::BAD_REQUEST, FaucetError::Internal(err)))?;
+        self.limiter
+            .check(recipient, client.ip(), amount)
+            .map_err(|err| {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    FaucetError::TooManyRequests(err),
+                )
+            })?;
+        Ok(amount)
+    }
+
+    /// Checkpoints and advances the clock as configured, after `transfers` gas
+    /// transfers were executed.
+    fn after_gas_sent(&self, s: &mut Simulacrum, transfers: usize) {
+        if transfers == 0 {
+            return;
+        }
+
+        match self.policy.checkpoint {
+            FaucetCheckpoint::Always => {
+                self.block_producer.create_checkpoint(s);
+            }
+            FaucetCheckpoint::BlockProduction => {
+                for _ in 0..transfers {
+                    self.block_producer.on_transaction_executed(s);
+                }
+            }
+            FaucetCheckpoint::Never => {}
+        }
+        if self.policy.advance_clock_ms > 0 {
+            s.advance_clock(Duration::from_millis(self.policy.advance_clock_ms));
+        }
+    }
+}
+
+struct BatchRequest {
+    task_id: Uuid,
+    recipient: IotaAddress,
+    amount: u64,
+}
+
+struct BatchTask {
+    status: BatchSendStatus,
+    finished_at: Option<Instant>,
+}
+
+/// The `/v1/gas` requests waiting for the next batch and the status of every task.
+#[derive(Default)]
+struct BatchQueue {
+    pending: Mutex<Vec<BatchRequest>>,
+    tasks: Mutex<HashMap<Uuid, BatchTask>>,
+    queued: Notify,
+}
+
+impl BatchQueue {
+    fn enqueue(&self, recipient: IotaAddress, amount: u64) -> Uuid {
+        let task_id = Uuid::new_v4();
+        self.tasks.lock().unwrap().insert(
+            task_id,
+            BatchTask {
+                status: BatchSendStatus {
+                    status: BatchSendStatusType::INPROGRESS,
+                    transferred_gas_objects: None,
+                },
+                finished_at: None,
+            },
+        );
+        self.pending.lock().unwrap().push(BatchRequest {
+            task_id,
+            recipient,
+            amount,
+        });
+        self.queued.notify_one();
+        task_id
+    }
+
+    fn status(&self, task_id: &Uuid) -> Option<BatchSendStatus> {
+        let tasks = self.tasks.lock().unwrap();
+        tasks.get(task_id).map(|task| task.status.clone())
+    }
+
+    /// Also forgets the tasks that finished more than [`BATCH_STATUS_TTL`] ago.
+    fn finish(&self, statuses: Vec<(Uuid, BatchSendStatus)>) {
+        let now = Instant::now();
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| {
+            task.finished_at
+                .is_none_or(|finished_at| now - finished_at < BATCH_STATUS_TTL)
+        });
+        for (task_id, status) in statuses {
+            let task = BatchTask {
+                status,
+                finished_at: Some(now),
+            };
+            tasks.insert(task_id, task);
+        }
+    }
+}
+
+/// A [`FaucetResponse`] that additionally carries the full references of the sent
+/// coins, so that they can be used as gas right away.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GasResponse {
+    #[serde(flatten)]
+    pub response: FaucetResponse,
+    pub transferred_gas_object_refs: Vec<CoinObjectRef>,
+    /// The checkpoint that includes the transfer, unless it is still pending
+    pub checkpoint: Option<CheckpointSequenceNumber>,
+}
+
+/// A [`BatchStatusFaucetResponse`] that additionally carries the checkpoint that includes
+/// the transfer of the task.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStatusResponse {
+    #[serde(flatten)]
+    pub response: BatchStatusFaucetResponse,
+    pub checkpoint: Option<CheckpointSequenceNumber>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CoinObjectRef {
+    pub object_id: ObjectID,
+    pub version: SequenceNumber,
+    pub digest: ObjectDigest,
+}
+
+impl From<ObjectRef> for CoinObjectRef {
+    fn from((object_id, version, digest): ObjectRef) -> Self {
+        Self {
+            object_id,
+            version,
+            digest,
+        }
+    }
+}
+
+/// Query parameters of the gas endpoint, e.g. `/gas?amount=5000000000`.
+#[derive(Deserialize, Debug, Default)]
+struct GasQuery {
+    amount: Option<u64>,
+}
 
 async fn health() -> &'static str {
     "OK"
 }
 
 async fn request_gas(
-    State(state): State<Arc<RwLock<Simulacrum>>>, // Use the trait object
+    State(state): State<FaucetState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(query): Query<GasQuery>,
     Json(payload): Json<FaucetRequest>,
-) -> impl IntoResponse {
-    let result = match payload {
-        FaucetRequest::FixedAmountRequest(requests) => {
-            let mut s = state.write().unwrap();
-            let res = s.request_gas(requests.recipient, 2000000000);
-            s.create_checkpoint();
-            s.advance_clock(Duration::new(5, 0));
-            res
-        }
-        _ => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(FaucetResponse::from(FaucetError::Internal(
-                    "Input Error.".to_string(),
-                ))),
-            );
+) -> Response {
+    let recipient = match payload {
+        FaucetRequest::FixedAmountRequest(request) => request.recipient,
+        FaucetRequest::GetBatchSendStatusRequest(request) => {
+            return batch_status(&state, &request.task_id);
         }
     };
+    let amount = match state.admit(recipient, client, query.amount) {
+        Ok(amount) => amount,
+        Err((status, err)) => return error_response(status, err),
+    };
+
+    let result = {
+        let mut s = state.sim.write().unwrap();
+        send_gas(&mut s, recipient, amount).map(|(sent, object_refs)| {
+            state.after_gas_sent(&mut s, 1);
+            let checkpoint = transfer_checkpoint(&s, &sent);
+            (sent, object_refs, checkpoint)
+        })
+    };
 
     match result {
-        Ok(o) => {
-            println!("{:?}", o.summary_for_debug());
-            println!("Request is successfully served");
-            (
-                StatusCode::CREATED,
-                Json(FaucetResponse::from(FaucetReceipt { sent: Vec::new() })),
-            )
+        Ok((sent, object_refs, checkpoint)) => {
+            println!("Sent {amount} NANOS to {recipient}");
+            let response = GasResponse {
+                response: FaucetResponse::from(FaucetReceipt { sent }),
+                transferred_gas_object_refs: object_refs,
+                checkpoint,
+            };
+            (StatusCode::CREATED, Json(response)).into_response()
         }
         Err(err) => {
             println!("Failed to request gas: {:?}", err);
-            (
+            error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(FaucetResponse {
-                    error: None,
-                    transferred_gas_objects: Vec::new(),
-                }),
+                FaucetError::Internal(err.to_string()),
             )
         }
     }
 }
 
-pub async fn start_fake_faucet(sim: Arc<RwLock<Simulacrum>>) -> std::io::Result<()> {
+/// Queues the request for the next batch and returns the ID of its task.
+async fn batch_request_gas(
+    State(state): State<FaucetState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(query): Query<GasQuery>,
+    Json(payload): Json<FaucetRequest>,
+) -> Response {
+    let recipient = match payload {
+        FaucetRequest::FixedAmountRequest(request) => request.recipient,
+        FaucetRequest::GetBatchSendStatusRequest(request) => {
+            return batch_status(&state, &request.task_id);
+        }
+    };
+    let amount = match state.admit(recipient, client, query.amount) {
+        Ok(amount) => amount,
+        Err((status, err)) => {
+            return (status, Json(BatchFaucetResponse::from(err))).into_response();
+        }
+    };
+
+    let task_id = state.batches.enqueue(recipient, amount);
+    let response = BatchFaucetResponse {
+        task: Some(task_id.to_string()),
+        error: None,
+    };
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
+
+async fn request_status(State(state): State<FaucetState>, Path(task_id): Path<String>) -> Response {
+    batch_status(&state, &task_id)
+}
+
+fn batch_status(state: &FaucetState, task_id: &str) -> Response {
+    let status = match Uuid::parse_str(task_id) {
+        Ok(task_id) => state
+            .batches
+            .status(&task_id)
+            .ok_or((StatusCode::NOT_FOUND, format!("unknown task {task_id}"))),
+        Err(err) => Err((StatusCode::BAD_REQUEST, format!("invalid task id: {err}"))),
+    };
+
+    match status {
+        Ok(status) => {
+            // Looked up now, as the batch may have been checkpointed since it was served
+            let checkpoint = status
+                .transferred_gas_objects
+                .as_ref()
+                .and_then(|receipt| transfer_checkpoint(&state.sim.read().unwrap(), &receipt.sent));
+            let response = BatchStatusResponse {
+                response: BatchStatusFaucetResponse {
+                    status: Some(status),
+                    error: None,
+                },
+                checkpoint,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err((code, message)) => {
+            let response = BatchStatusResponse {
+                response: BatchStatusFaucetResponse {
+                    status: None,
+                    error: Some(message),
+                },
+                checkpoint: None,
+            };
+            (code, Json(response)).into_response()
+        }
+    }
+}
+
+fn transfer_checkpoint(s: &Simulacrum, sent: &[CoinInfo]) -> Option<CheckpointSequenceNumber> {
+    let coin = sent.first()?;
+    s.get_transaction_checkpoint(&coin.transfer_tx_digest)
+        .ok()
+        .flatten()
+}
+
+/// Serves the queued `/v1/gas` requests, each batch under a single write lock and
+/// followed by the side effects of a single request.
+async fn run_batch_worker(state: FaucetState, cancellation_token: CancellationToken) {
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return,
+            _ = state.batches.queued.notified() => {}
+        }
+        // Give concurrent requests the chance to join the batch
+        tokio::time::sleep(BATCH_WINDOW).await;
+
+        let requests = std::mem::take(&mut *state.batches.pending.lock().unwrap());
+        if requests.is_empty() {
+            continue;
+        }
+
+        let results: Vec<_> = {
+            let mut s = state.sim.write().unwrap();
+            let results: Vec<_> = requests
+                .into_iter()
+                .map(|request| {
+                    let result = send_gas(&mut s, request.recipient, request.amount);
+                    (request.task_id, result)
+                })
+                .collect();
+            let transfers = results.iter().filter(|(_, result)| result.is_ok()).count();
+            state.after_gas_sent(&mut s, transfers);
+            results
+        };
+
+        let statuses = results
+            .into_iter()
+            .map(|(task_id, result)| {
+                let status = match result {
+                    Ok((sent, _)) => BatchSendStatus {
+                        status: BatchSendStatusType::SUCCEEDED,
+                        transferred_gas_objects: Some(FaucetReceipt { sent }),
+                    },
+                    Err(err) => {
+                        println!("Failed to serve batch task {task_id}: {:?}", err);
+                        BatchSendStatus {
+                            status: BatchSendStatusType::DISCARDED,
+                            transferred_gas_objects: None,
+                        }
+                    }
+                };
+                (task_id, status)
+            })
+            .collect();
+        state.batches.finish(statuses);
+    }
+}
+
+/// Transfers a new gas coin to `recipient` and describes it from the effects.
+fn send_gas(
+    s: &mut Simulacrum,
+    recipient: IotaAddress,
+    amount: u64,
+) -> anyhow::Result<(Vec<CoinInfo>, Vec<CoinObjectRef>)> {
+    let effects = s.request_gas(recipient, amount)?;
+    let transfer_tx_digest = *effects.transaction_digest();
+    if effects.status().is_err() {
+        bail!(
+            "gas transfer {transfer_tx_digest} failed: {:?}",
+            effects.status()
+        );
+    }
+
+    let sent = effects
+        .created()
+        .into_iter()
+        .filter(|(_, owner)| *owner == Owner::AddressOwner(recipient))
+        .filter_map(|(object_ref, _)| {
+            let object = s.get_object(&object_ref.0).ok().flatten()?;
+            object.is_gas_coin().then(|| {
+                let coin = CoinInfo {
+                    amount: object.get_coin_value_unsafe(),
+                    id: object_ref.0,
+                    transfer_tx_digest,
+                };
+                (coin, CoinObjectRef::from(object_ref))
+            })
+        })
+        .unzip();
+
+    Ok(sent)
+}
+
+/// The amount to send, falling back to the configured default.
+fn requested_amount(policy: &FaucetPolicy, amount: Option<u64>) -> Result<u64, String> {
+    match amount {
+        None => Ok(policy.amount),
+        Some(0) => Err("amount must be positive".to_string()),
+        Some(amount) if amount > policy.max_amount => Err(format!(
+            "amount {amount} exceeds the maximum of {}",
+            policy.max_amount
+        )),
+        Some(amount) => Ok(amount),
+    }
+}
+
+fn error_response(status: StatusCode, err: FaucetError) -> Response {
+    let response = GasResponse {
+        response: FaucetResponse::from(err),
+        transferred_gas_object_refs: Vec::new(),
+        checkpoint: None,
+    };
+    (status, Json(response)).into_response()
+}
+
+pub async fn start_fake_faucet(
+    state: FaucetState,
+    listener: tokio::net::TcpListener,
+    cancellation_token: CancellationToken,
+) -> std::io::Result<()> {
+    let batch_worker = tokio::spawn(run_batch_worker(state.clone(), cancellation_token.clone()));
+
     let app = Router::new()
         .route("/", get(health))
         .route("/gas", post(request_gas))
-        .with_state(sim);
-
-    let listener = tokio::net::TcpListener::bind(get_faucet_binding_ip())
-        .await
-        .unwrap();
+        .route("/v1/gas", post(batch_request_gas))
+        .route("/v1/status/{task_id}", get(request_status))
+        .with_state(state);
 
-    axum::serve(listener, app).await
+    // The client address is needed for the per-IP limits
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let result = axum::serve(listener, app)
+        .with_graceful_shutdown(cancellation_token.cancelled_owned())
+        .await;
+    _ = batch_worker.await;
+    result
 }
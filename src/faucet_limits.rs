@@ -0,0 +1,235 @@
+use crate::config::FaucetLimits;
+use iota_types::base_types::IotaAddress;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Address(IotaAddress),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Counter {
+    Requests,
+    DailyAmount,
+}
+
+/// Usage of a client within a fixed window that starts with its first request.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    used: u64,
+}
+
+impl Window {
+    /// The usage after adding `amount`, restarting the window once it elapsed.
+    fn add(window: Option<&Window>, now: Instant, length: Duration, amount: u64) -> Window {
+        match window {
+            Some(window) if now.duration_since(window.started) < length => Window {
+                started: window.started,
+                used: window.used.saturating_add(amount),
+            },
+            _ => Window {
+                started: now,
+                used: amount,
+            },
+        }
+    }
+
+    fn retry_after(&self, now: Instant, length: Duration) -> Duration {
+        length.saturating_sub(now.duration_since(self.started))
+    }
+}
+
+/// Enforces the configured [`FaucetLimits`]. Shared with the control API, which can
+/// reset the counters between tests.
+pub struct FaucetLimiter {
+    limits: FaucetLimits,
+    windows: Mutex<HashMap<(Counter, Client), Window>>,
+}
+
+impl FaucetLimiter {
+    pub fn new(limits: FaucetLimits) -> Self {
+        Self {
+            limits,
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Counts a request of `amount` NANOS for `recipient` from `ip`, unless it exceeds
+    /// one of the limits, in which case nothing is counted.
+    pub fn check(&self, recipient: IotaAddress, ip: IpAddr, amount: u64) -> Result<(), String> {
+        self.check_at(Instant::now(), recipient, ip, amount)
+    }
+
+    fn check_at(
+        &self,
+        now: Instant,
+        recipient: IotaAddress,
+        ip: IpAddr,
+        amount: u64,
+    ) -> Result<(), String> {
+        let rate_limit_window = Duration::from_secs(self.limits.window_secs);
+        let limits = [
+            (
+                Counter::Requests,
+                Client::Address(recipient),
+                self.limits.requests_per_address.map(u64::from),
+            ),
+            (
+                Counter::Requests,
+                Client::Ip(ip),
+                self.limits.requests_per_ip.map(u64::from),
+            ),
+            (
+                Counter::DailyAmount,
+                Client::Address(recipient),
+                self.limits.daily_amount_per_address,
+            ),
+            (
+                Counter::DailyAmount,
+                Client::Ip(ip),
+                self.limits.daily_amount_per_ip,
+            ),
+        ];
+
+        let mut windows = self.windows.lock().unwrap();
+        let mut updated = Vec::new();
+        for (counter, client, limit) in limits {
+            let Some(limit) = limit else {
+                continue;
+            };
+            let (length, increment) = match counter {
+                Counter::Requests => (rate_limit_window, 1),
+                Counter::DailyAmount => (DAY, amount),
+            };
+
+            let window = Window::add(windows.get(&(counter, client)), now, length, increment);
+            if window.used > limit {
+                let retry_after = window.retry_after(now, length).as_secs();
+                return Err(match counter {
+                    Counter::Requests => format!(
+                        "{} exceeded {limit} requests per {}s, retry in {retry_after}s",
+                        describe(client),
+                        length.as_secs()
+                    ),
+                    Counter::DailyAmount => format!(
+                        "{} exceeded its daily quota of {limit} NANOS, retry in {retry_after}s",
+                        describe(client)
+                    ),
+                });
+            }
+            updated.push(((counter, client), window));
+        }
+
+        windows.extend(updated);
+        Ok(())
+    }
+
+    /// Forgets all requests made so far.
+    pub fn reset(&self) {
+        self.windows.lock().unwrap().clear();
+    }
+}
+
+fn describe(client: Client) -> String {
+    match client {
+        Client::Address(address) => format!("Address {address}"),
+        Client::Ip(ip) => format!("Client {ip}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn limiter(limits: FaucetLimits) -> FaucetLimiter {
+        FaucetLimiter::new(FaucetLimits {
+            window_secs: 60,
+            ..limits
+        })
+    }
+
+    #[test]
+    fn requests_are_limited_until_the_window_rolls_over() {
+        let limiter = limiter(FaucetLimits {
+            requests_per_address: Some(2),
+            ..Default::default()
+        });
+        let recipient = IotaAddress::random_for_testing_only();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(now, recipient, IP, 1).is_ok());
+        assert!(limiter.check_at(now, recipient, IP, 1).is_ok());
+        let err = limiter
+            .check_at(now + Duration::from_secs(59), recipient, IP, 1)
+            .unwrap_err();
+        assert!(err.contains("retry in 1s"), "{err}");
+
+        let rolled_over = now + Duration::from_secs(60);
+        assert!(limiter.check_at(rolled_over, recipient, IP, 1).is_ok());
+        assert!(limiter.check_at(rolled_over, recipient, IP, 1).is_ok());
+        assert!(limiter.check_at(rolled_over, recipient, IP, 1).is_err());
+    }
+
+    #[test]
+    fn daily_quota_counts_amounts_per_day() {
+        let limiter = limiter(FaucetLimits {
+            daily_amount_per_address: Some(100),
+            ..Default::default()
+        });
+        let recipient = IotaAddress::random_for_testing_only();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(now, recipient, IP, 60).is_ok());
+        assert!(limiter.check_at(now, recipient, IP, 50).is_err());
+        assert!(limiter.check_at(now, recipient, IP, 40).is_ok());
+        assert!(limiter
+            .check_at(now + Duration::from_secs(60), recipient, IP, 1)
+            .is_err());
+
+        assert!(limiter.check_at(now + DAY, recipient, IP, 100).is_ok());
+        // Other addresses have their own quota
+        let other = IotaAddress::random_for_testing_only();
+        assert!(limiter.check_at(now, other, IP, 100).is_ok());
+    }
+
+    #[test]
+    fn rejected_requests_consume_nothing() {
+        let limiter = limiter(FaucetLimits {
+            requests_per_address: Some(1),
+            requests_per_ip: Some(1),
+            daily_amount_per_ip: Some(100),
+            ..Default::default()
+        });
+        let recipient = IotaAddress::random_for_testing_only();
+        let now = Instant::now();
+
+        // Passes the request limits, but not the daily quota
+        assert!(limiter.check_at(now, recipient, IP, 200).is_err());
+        assert!(limiter.check_at(now, recipient, IP, 100).is_ok());
+        assert!(limiter.check_at(now, recipient, IP, 1).is_err());
+    }
+
+    #[test]
+    fn reset_forgets_all_requests() {
+        let limiter = limiter(FaucetLimits {
+            requests_per_ip: Some(1),
+            ..Default::default()
+        });
+        let recipient = IotaAddress::random_for_testing_only();
+
+        assert!(limiter.check(recipient, IP, 1).is_ok());
+        assert!(limiter.check(recipient, IP, 1).is_err());
+        limiter.reset();
+        assert!(limiter.check(recipient, IP, 1).is_ok());
+    }
+}
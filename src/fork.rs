@@ -0,0 +1,87 @@
+use crate::config::ForkConfig;
+use crate::simulacrum::Simulacrum;
+use anyhow::Context;
+use iota_storage::blob::Blob;
+use iota_types::base_types::ObjectID;
+use iota_types::full_checkpoint_content::CheckpointData;
+use iota_types::object::Object;
+use iota_types::{
+    is_system_package, IOTA_AUTHENTICATOR_STATE_OBJECT_ID, IOTA_CLOCK_OBJECT_ID,
+    IOTA_DENY_LIST_OBJECT_ID, IOTA_RANDOMNESS_STATE_OBJECT_ID, IOTA_SYSTEM_STATE_OBJECT_ID,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Objects that belong to the local genesis and must not be replaced by the ones of the
+/// forked network.
+const SYSTEM_OBJECTS: [ObjectID; 5] = [
+    IOTA_SYSTEM_STATE_OBJECT_ID,
+    IOTA_CLOCK_OBJECT_ID,
+    IOTA_AUTHENTICATOR_STATE_OBJECT_ID,
+    IOTA_RANDOMNESS_STATE_OBJECT_ID,
+    IOTA_DENY_LIST_OBJECT_ID,
+];
+
+/// Writes the objects of a real network into the store of a fresh chain, which then
+/// continues with local checkpoints on top. The object snapshot is applied first and the
+/// checkpoints in order of their sequence number after it, so that later versions win.
+///
+/// The checkpoints of the real network are signed by its own committee, so only their
+/// objects are taken over, not the checkpoints themselves.
+pub fn fork(sim: &mut Simulacrum, config: &ForkConfig) -> anyhow::Result<()> {
+    let mut objects = BTreeMap::new();
+
+    if let Some(path) = &config.objects {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("failed to read object snapshot {}", path.display()))?;
+        let snapshot: Vec<Object> = bcs::from_bytes(&bytes)
+            .with_context(|| format!("invalid object snapshot {}", path.display()))?;
+        objects.extend(snapshot.into_iter().map(|object| (object.id(), object)));
+    }
+
+    if let Some(dir) = &config.checkpoints {
+        for path in checkpoint_files(dir)? {
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("failed to read checkpoint {}", path.display()))?;
+            let checkpoint: CheckpointData = Blob::from_bytes(&bytes)
+                .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+
+            for object in checkpoint.latest_live_output_objects() {
+                objects.insert(object.id(), object.clone());
+            }
+            for (object_id, _, _) in checkpoint.eventually_removed_object_refs_post_version() {
+                objects.remove(&object_id);
+            }
+        }
+    }
+
+    objects.retain(|object_id, _| {
+        !SYSTEM_OBJECTS.contains(object_id) && !is_system_package(*object_id)
+    });
+    println!("Forking {} objects", objects.len());
+
+    sim.update_objects(objects, Vec::new());
+    sim.create_checkpoint();
+    Ok(())
+}
+
+/// Ordered by sequence number.
+fn checkpoint_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read checkpoint dir {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "chk") {
+            let sequence_number: u64 = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+                .with_context(|| format!("unexpected checkpoint file {}", path.display()))?;
+            files.push((sequence_number, path));
+        }
+    }
+
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
@@ -0,0 +1,186 @@
+use crate::simulacrum::InnerSimulacrum;
+use anyhow::Context;
+use iota_types::base_types::{IotaAddress, ObjectID, ObjectRef};
+use iota_types::object::Object;
+use iota_types::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A change made to the simulated chain, recorded so that the chain can be rebuilt from
+/// its seed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Operation {
+    ExecuteTransaction(Transaction),
+    ExecuteTransactionImpersonating(Transaction),
+    CreateCheckpoint,
+    AdvanceClock(Duration),
+    AdvanceEpoch,
+    RequestGas {
+        recipient: IotaAddress,
+        amount: u64,
+    },
+    UpdateObjects {
+        written: BTreeMap<ObjectID, Object>,
+        deleted: Vec<ObjectRef>,
+    },
+}
+
+impl Operation {
+    /// Applies the operation again. It succeeded when it was recorded, so an error means
+    /// that the rebuilt chain diverged.
+    pub fn replay(&self, sim: &mut InnerSimulacrum) -> anyhow::Result<()> {
+        match self {
+            Self::ExecuteTransaction(transaction) => {
+                sim.execute_transaction(transaction.clone())?;
+            }
+            Self::ExecuteTransactionImpersonating(transaction) => {
+                sim.execute_transaction_impersonating(transaction.clone())?;
+            }
+            Self::CreateCheckpoint => {
+                sim.create_checkpoint();
+            }
+            Self::AdvanceClock(duration) => {
+                sim.advance_clock(*duration);
+            }
+            Self::AdvanceEpoch => sim.advance_epoch(),
+            Self::RequestGas { recipient, amount } => {
+                sim.request_gas(*recipient, *amount)?;
+            }
+            Self::UpdateObjects { written, deleted } => {
+                sim.store_mut()
+                    .update_objects(written.clone(), deleted.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends operations to a journal file as they are recorded. Every operation is stored
+/// as its BCS encoding, prefixed with the length as a little endian `u32`.
+pub struct JournalWriter {
+    path: PathBuf,
+    file: File,
+    /// Length of the complete records written so far
+    len: u64,
+}
+
+impl JournalWriter {
+    /// Replaces the journal file with `operations` and appends to it from then on. The
+    /// new journal is written next to the old one and then moved over it, so that a
+    /// killed simulator leaves either of them behind.
+    pub fn create(path: &Path, operations: &[Operation]) -> anyhow::Result<Self> {
+        let temp_path = path.with_extension("tmp");
+        let file = File::create(&temp_path)
+            .with_context(|| format!("failed to create journal {}", temp_path.display()))?;
+        let mut writer = Self {
+            path: path.to_path_buf(),
+            file,
+            len: 0,
+        };
+        for operation in operations {
+            writer.write_record(operation)?;
+        }
+        writer.file.sync_data()?;
+        // The file stays open, so appending continues in the renamed file
+        std::fs::rename(&temp_path, path)
+            .with_context(|| format!("failed to replace journal {}", path.display()))?;
+        Ok(writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Synced right away, so that a killed simulator loses at most the operation it was
+    /// writing. If that fails, the partially written record is truncated again.
+    pub fn append(&mut self, operation: &Operation) -> anyhow::Result<()> {
+        let len = self.len;
+        let result = self
+            .write_record(operation)
+            .and_then(|()| Ok(self.file.sync_data()?));
+        if result.is_err() {
+            self.len = len;
+            _ = self.file.set_len(len);
+        }
+        result
+    }
+
+    fn write_record(&mut self, operation: &Operation) -> anyhow::Result<()> {
+        let bytes = bcs::to_bytes(operation)?;
+        let mut record = Vec::with_capacity(4 + bytes.len());
+        record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&bytes);
+        self.file.write_all(&record)?;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Reads a journal file written by [`JournalWriter`]. A truncated last record, left
+/// behind when the simulator was killed while writing it, is dropped.
+pub fn read_journal(path: &Path) -> anyhow::Result<Vec<Operation>> {
+    let content = std::fs::read(path)
+        .with_context(|| format!("failed to read journal {}", path.display()))?;
+
+    let mut operations = Vec::new();
+    let mut rest = content.as_slice();
+    while !rest.is_empty() {
+        let record = rest.get(4..).and_then(|tail| {
+            let length = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            tail.get(..length as usize)
+        });
+        let Some(record) = record else {
+            println!(
+                "Dropping the incomplete last operation of journal {}",
+                path.display()
+            );
+            break;
+        };
+        operations.push(
+            bcs::from_bytes(record)
+                .with_context(|| format!("corrupt journal {}", path.display()))?,
+        );
+        rest = &rest[4 + record.len()..];
+    }
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_last_record_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.bcs");
+        let mut writer = JournalWriter::create(&path, &[Operation::CreateCheckpoint]).unwrap();
+        writer
+            .append(&Operation::AdvanceClock(Duration::from_secs(5)))
+            .unwrap();
+        writer.append(&Operation::AdvanceEpoch).unwrap();
+
+        let complete = read_journal(&path).unwrap();
+        assert_eq!(complete.len(), 3);
+
+        // Cut into the last record, as a simulator killed while writing it would
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let operations = read_journal(&path).unwrap();
+        assert_eq!(operations.len(), 2);
+        assert!(matches!(operations[0], Operation::CreateCheckpoint));
+        assert!(matches!(
+            operations[1],
+            Operation::AdvanceClock(duration) if duration == Duration::from_secs(5)
+        ));
+    }
+}
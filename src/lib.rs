@@ -0,0 +1,25 @@
+mod archive;
+pub mod block_production;
+pub mod config;
+pub mod consts;
+pub mod dev_accounts;
+mod executor;
+mod fake_faucet;
+mod faucet_limits;
+mod fork;
+mod journal;
+mod managed_postgres;
+mod move_package;
+pub mod scenario;
+mod seeding;
+pub mod simulacrum;
+mod simulacrum_control_api;
+mod simulacum_reader_wrapper;
+pub mod simulator;
+mod state_dir;
+mod state_overrides;
+mod transactions;
+
+pub use crate::config::{Endpoints, SimulatorConfig};
+pub use crate::simulacrum::Simulacrum;
+pub use crate::simulator::{SimulatorBuilder, SimulatorHandle};
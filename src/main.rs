@@ -1,84 +1,85 @@
-use ::simulacrum::Simulacrum;
-use std::ops::Deref;
-use std::sync::{Arc, OnceLock};
-use std::sync::atomic::{AtomicBool, Ordering};
-use crate::consts::{get_control_url, get_faucet_url, get_indexer_client_url, get_rpc_client_url};
-use signal_hook::flag;
-
-mod consts;
-mod fake_faucet;
-mod simulacrum;
-mod simulacrum_control_api;
-mod simulacum_reader_wrapper;
-
-static EXTENDED_API_SHARED_SIMULACRUM_INITIALIZED_ENV: OnceLock<simulacrum::SimulacrumTestSetup> =
-    OnceLock::new();
-
-fn get_or_init_shared_extended_api_simulacrum_env() -> &'static simulacrum::SimulacrumTestSetup {
-    simulacrum::SimulacrumTestSetup::get_or_init(
-        "extended_api",
-        |data_ingestion_path| {
-            let mut sim = Simulacrum::new();
-
-            sim.set_data_ingestion_path(data_ingestion_path);
-
-            execute_simulacrum_transactions(&mut sim, 15);
-            add_checkpoints(&mut sim, 300);
-            sim.advance_epoch();
-
-            execute_simulacrum_transactions(&mut sim, 10);
-            add_checkpoints(&mut sim, 300);
-            sim.advance_epoch();
-
-            execute_simulacrum_transactions(&mut sim, 5);
-            add_checkpoints(&mut sim, 300);
-
-            sim
-        },
-        &EXTENDED_API_SHARED_SIMULACRUM_INITIALIZED_ENV,
-    )
+use anyhow::Context;
+use clap::Parser;
+use iota_l1_simulator::config::{Cli, Command, SimulatorConfig};
+use iota_l1_simulator::dev_accounts::DevAccounts;
+use iota_l1_simulator::simulacrum::SimulacrumTestSetup;
+use iota_l1_simulator::SimulatorBuilder;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// Prints the service endpoints as a single JSON line and writes them to the ready-file,
+/// so that test harnesses can discover the simulator.
+fn publish_endpoints(config: &SimulatorConfig) -> anyhow::Result<()> {
+    let endpoints = serde_json::to_string(&config.endpoints())?;
+    println!("{endpoints}");
+
+    if let Some(ready_file) = &config.ready_file {
+        // Write to a temporary file first, so that the ready-file never appears half-written
+        let tmp_file = ready_file.with_extension("tmp");
+        std::fs::write(&tmp_file, &endpoints)
+            .and_then(|()| std::fs::rename(&tmp_file, ready_file))
+            .with_context(|| format!("failed to write the ready-file {}", ready_file.display()))?;
+    }
+    Ok(())
 }
 
-fn execute_simulacrum_transaction(sim: &mut Simulacrum) {
-    let transfer_recipient = iota_types::base_types::IotaAddress::random_for_testing_only();
-    let (transaction, _) = sim.transfer_txn(transfer_recipient);
-    sim.execute_transaction(transaction.clone()).unwrap();
-}
+fn print_dev_accounts(dev_accounts: &DevAccounts) {
+    let info = dev_accounts.info();
+    let Some(mnemonic) = info.mnemonic else {
+        return;
+    };
 
-fn execute_simulacrum_transactions(sim: &mut Simulacrum, transactions_count: u32) {
-    for _ in 0..transactions_count {
-        execute_simulacrum_transaction(sim);
-    }
-}
-fn add_checkpoints(sim: &mut Simulacrum, checkpoints_count: i32) {
-    // Main use of this function is to create more checkpoints than the current
-    // processing batch size, to circumvent the issue described in
-    // https://github.com/iotaledger/iota/issues/2197#issuecomment-2376432709
-    for _ in 0..checkpoints_count {
-        sim.create_checkpoint();
+    println!("Dev accounts (mnemonic: {mnemonic}):");
+    for (index, account) in info.accounts.iter().enumerate() {
+        println!("({index}) {} {}", account.address, account.private_key);
     }
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     env_logger::init();
-    let mut sim = get_or_init_shared_extended_api_simulacrum_env();
+    let cli = Cli::parse();
+    let config = cli.load_config()?;
+
+    match cli.command.clone().unwrap_or_default() {
+        Command::Start => run(config)?,
+        Command::Config => print!("{}", toml::to_string_pretty(&config)?),
+        Command::Dump { output } => {
+            SimulatorBuilder::from_config(config)
+                .dump(&output)
+                .context("failed to dump the chain")?;
+            println!("Wrote {}", output.display());
+        }
+    }
+    Ok(())
+}
 
-    println!("Indexer: {}", get_indexer_client_url());
-    println!("RPC URL: {}", get_rpc_client_url());
-    println!("Faucet URL: {}", get_faucet_url());
-    println!("RPC URL: {}", get_control_url());
+fn run(config: SimulatorConfig) -> anyhow::Result<()> {
+    // Registered before starting, so that a signal during the start is not lost but
+    // shuts the simulator down once it runs
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT]).context("failed to register signal handlers")?;
+    let setup = SimulacrumTestSetup::try_new(SimulatorBuilder::from_config(config))
+        .context("failed to start the simulator")?;
+    let config = setup.handle.config();
+
+    match config.indexer_enabled() {
+        true => println!("Indexer: {}", config.client_url(&config.indexer)),
+        false => println!("Indexer: disabled"),
+    }
+    println!("RPC URL: {}", config.client_url(&config.rpc));
+    println!("Faucet URL: {}", config.client_url(&config.faucet));
+    println!("Control URL: {}", config.client_url(&config.control));
+    print_dev_accounts(setup.handle.dev_accounts());
 
+    publish_endpoints(config)?;
     println!("Simulacrum Server running!");
 
     // Waiting for Ctrl-C (or Docker SIGTERM) to exit
-    let term = Arc::new(AtomicBool::new(false));
+    signals.forever().next();
 
-    flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term)).expect("Failed to register SIGTERM handler");
-    flag::register(signal_hook::consts::SIGINT, Arc::clone(&term)).expect("Failed to register SIGINT handler");
-
-    while !Arc::clone(&term).load(Ordering::Relaxed) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
+    println!("Shutting down...");
+    setup.shutdown();
 
     println!("Exiting...");
+    Ok(())
 }
@@ -0,0 +1,131 @@
+use anyhow::{bail, Context};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::{tempdir, TempDir};
+
+const START_ATTEMPTS: u32 = 5;
+
+/// A throwaway Postgres server for [`DatabaseMode::Managed`], so that developers do not
+/// need a system database. The cluster lives in a temporary directory and is stopped and
+/// removed again when this is dropped.
+///
+/// [`DatabaseMode::Managed`]: crate::config::DatabaseMode::Managed
+pub struct ManagedPostgres {
+    pg_ctl: PathBuf,
+    port: u16,
+    dir: TempDir,
+}
+
+impl ManagedPostgres {
+    /// Creates a cluster with `initdb` and starts it with `pg_ctl` on a free local port.
+    /// Both are taken from `bin_dir`, or from `PATH` if it is `None`.
+    pub fn start(bin_dir: Option<&Path>) -> anyhow::Result<Self> {
+        let binary = |name: &str| match bin_dir {
+            Some(bin_dir) => bin_dir.join(name),
+            None => PathBuf::from(name),
+        };
+
+        let dir = tempdir()?;
+        let data_dir = dir.path().join("data");
+        run(Command::new(binary("initdb"))
+            .arg("--pgdata")
+            .arg(&data_dir)
+            .args([
+                "--username=postgres",
+                "--auth=trust",
+                "--encoding=UTF8",
+                "--no-sync",
+            ]))?;
+
+        let log_path = dir.path().join("postgres.log");
+        // Created before starting, so that a server that failed to come up is stopped too
+        let mut postgres = Self {
+            pg_ctl: binary("pg_ctl"),
+            port: 0,
+            dir,
+        };
+
+        // Postgres binds the port itself, so a free one is looked up like the ephemeral
+        // service ports. Another process can take it meanwhile, so starting is retried.
+        for attempt in 1..=START_ATTEMPTS {
+            postgres.port = free_port()?;
+            _ = std::fs::remove_file(&log_path);
+            let Err(err) = postgres.pg_ctl_start(&data_dir, &log_path) else {
+                break;
+            };
+
+            let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+            if attempt == START_ATTEMPTS || !log.contains("Address already in use") {
+                return Err(err.context(format!("Postgres did not start:\n{}", log.trim())));
+            }
+        }
+
+        println!(
+            "Started a managed Postgres server on port {}",
+            postgres.port
+        );
+        Ok(postgres)
+    }
+
+    fn pg_ctl_start(&self, data_dir: &Path, log_path: &Path) -> anyhow::Result<()> {
+        // The data is thrown away on exit, so durability is traded for speed
+        let options = format!(
+            "-p {} -k '{}' -c listen_addresses=127.0.0.1 -c lc_messages=C -c fsync=off \
+             -c synchronous_commit=off -c full_page_writes=off",
+            self.port,
+            self.dir.path().display()
+        );
+        run(Command::new(&self.pg_ctl)
+            .arg("start")
+            .arg("--pgdata")
+            .arg(data_dir)
+            .arg("--log")
+            .arg(log_path)
+            .args(["--wait", "--silent", "-o"])
+            .arg(options))
+    }
+
+    /// The server URL, without a database name.
+    pub fn url(&self) -> String {
+        format!("postgres://postgres@127.0.0.1:{}", self.port)
+    }
+}
+
+impl Drop for ManagedPostgres {
+    fn drop(&mut self) {
+        let data_dir = self.dir.path().join("data");
+        if !data_dir.join("postmaster.pid").exists() {
+            return;
+        }
+
+        let stopped = Command::new(&self.pg_ctl)
+            .arg("stop")
+            .arg("--pgdata")
+            .arg(&data_dir)
+            .args(["--mode=fast", "--wait", "--silent"])
+            .status();
+        if !stopped.is_ok_and(|status| status.success()) {
+            println!("Failed to stop the managed Postgres server");
+        }
+    }
+}
+
+fn free_port() -> std::io::Result<u16> {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))?;
+    Ok(listener.local_addr()?.port())
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().with_context(|| {
+        format!("failed to run {program}; install Postgres or set --postgres-bin-dir")
+    })?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::simulacrum::Simulacrum;
+use crate::transactions::{build_programmable_transaction, sign_and_execute};
+use anyhow::anyhow;
+use iota_move_build::{BuildConfig, IotaPackageHooks};
+use iota_types::base_types::{IotaAddress, ObjectID, SequenceNumber};
+use iota_types::crypto::IotaKeyPair;
+use iota_types::digests::{ObjectDigest, TransactionDigest};
+use iota_types::effects::{TransactionEffects, TransactionEffectsAPI};
+use iota_types::object::Owner;
+use iota_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use iota_types::storage::ObjectStore;
+use iota_types::transaction::ProgrammableTransaction;
+use move_core_types::account_address::AccountAddress;
+use move_package::package_hooks::register_package_hooks;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A compiled Move package, ready to be published.
+pub struct BuiltPackage {
+    pub modules: Vec<Vec<u8>>,
+    pub dependencies: Vec<ObjectID>,
+}
+
+/// Compiles the Move package in `path`. `named_addresses` are added to the ones declared
+/// in its `Move.toml`.
+pub fn build_move_package(
+    path: &Path,
+    named_addresses: BTreeMap<String, AccountAddress>,
+) -> anyhow::Result<BuiltPackage> {
+    register_package_hooks(Box::new(IotaPackageHooks));
+    // The build output goes to a directory removed afterwards, not into the package
+    let install_dir = tempfile::tempdir()?;
+    let mut build_config = BuildConfig::default();
+    build_config.config.install_dir = Some(install_dir.path().to_path_buf());
+    build_config.config.additional_named_addresses = named_addresses;
+
+    let package = build_config
+        .build(path)
+        .map_err(|err| anyhow!("failed to build {}: {err}", path.display()))?;
+
+    Ok(BuiltPackage {
+        modules: package.get_package_bytes(false),
+        dependencies: package.get_dependency_storage_package_ids(),
+    })
+}
+
+/// Publishes the package and hands its `UpgradeCap` to `sender`.
+pub fn publish_transaction(package: BuiltPackage, sender: IotaAddress) -> ProgrammableTransaction {
+    let mut builder = ProgrammableTransactionBuilder::new();
+    let upgrade_cap = builder.publish_upgradeable(package.modules, package.dependencies);
+    builder.transfer_arg(sender, upgrade_cap);
+    builder.finish()
+}
+
+/// A created object, as reported after publishing.
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatedObject {
+    pub object_id: ObjectID,
+    pub version: SequenceNumber,
+    pub digest: ObjectDigest,
+    pub owner: Owner,
+    /// The Move type, or `package` for the published package itself
+    pub object_type: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PublishedPackage {
+    pub package_id: ObjectID,
+    pub upgrade_cap: ObjectID,
+    pub digest: TransactionDigest,
+    /// All objects created by the publish transaction, including the package, the
+    /// `UpgradeCap` and the objects created by module initializers
+    pub created: Vec<CreatedObject>,
+}
+
+impl PublishedPackage {
+    /// Finds the package and its `UpgradeCap` among the objects a publish transaction
+    /// created.
+    pub fn from_effects(sim: &Simulacrum, effects: &TransactionEffects) -> anyhow::Result<Self> {
+        let created: Vec<_> = effects
+            .created()
+            .into_iter()
+            .filter_map(|((object_id, version, digest), owner)| {
+                let object = sim.get_object(&object_id).ok().flatten()?;
+                let object_type = match object.data.try_as_move() {
+                    Some(move_object) => move_object.type_().to_string(),
+                    None => "package".to_string(),
+                };
+                Some((
+                    object,
+                    CreatedObject {
+                        object_id,
+                        version,
+                        digest,
+                        owner,
+                        object_type,
+                    },
+                ))
+            })
+            .collect();
+
+        let package_id = created
+            .iter()
+            .find(|(object, _)| object.is_package())
+            .map(|(object, _)| object.id())
+            .ok_or_else(|| anyhow!("publish transaction did not create a package"))?;
+        let upgrade_cap = created
+            .iter()
+            .find(|(object, _)| {
+                object
+                    .data
+                    .try_as_move()
+                    .is_some_and(|move_object| move_object.type_().is_upgrade_cap())
+            })
+            .map(|(object, _)| object.id())
+            .ok_or_else(|| anyhow!("publish transaction did not create an UpgradeCap"))?;
+
+        Ok(Self {
+            package_id,
+            upgrade_cap,
+            digest: *effects.transaction_digest(),
+            created: created.into_iter().map(|(_, created)| created).collect(),
+        })
+    }
+}
+
+/// Builds and publishes the package in `path` from `sender`.
+pub fn publish_package(
+    sim: &mut Simulacrum,
+    path: &Path,
+    named_addresses: BTreeMap<String, AccountAddress>,
+    sender: IotaAddress,
+    keypair: &IotaKeyPair,
+) -> anyhow::Result<PublishedPackage> {
+    let package = build_move_package(path, named_addresses)?;
+    let pt = publish_transaction(package, sender);
+    let tx_data = build_programmable_transaction(sim, sender, pt)?;
+    let effects = sign_and_execute(sim, tx_data, keypair)?;
+
+    PublishedPackage::from_effects(sim, &effects)
+}
@@ -0,0 +1,426 @@
+use crate::move_package::publish_package;
+use crate::seeding::{add_checkpoints, execute_simulacrum_transactions};
+use crate::simulacrum::Simulacrum;
+use crate::transactions::{build_programmable_transaction, sign_and_execute};
+use anyhow::{anyhow, Context};
+use iota_types::base_types::{IotaAddress, ObjectID};
+use iota_types::crypto::{get_key_pair_from_rng, AccountKeyPair, IotaKeyPair};
+use iota_types::effects::TransactionEffects;
+use iota_types::object::Owner;
+use iota_types::parse_iota_type_tag;
+use iota_types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+use iota_types::storage::ObjectStore;
+use iota_types::transaction::{Argument, ObjectArg, ProgrammableTransaction};
+use move_core_types::account_address::AccountAddress;
+use move_core_types::identifier::Identifier;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+/// The initial chain state, described in a YAML or JSON file. See the README for an
+/// example.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Accounts whose keys are generated (from `--seed`, if given) and that can be
+    /// referred to by name in the steps.
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AccountSpec>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AccountSpec {
+    /// Initial balance in NANOS, sent from the simulator's own accounts
+    #[serde(default)]
+    pub balance: u64,
+}
+
+/// Wherever an address is expected, the name of a scenario account, the name of a
+/// published package or a hex address can be used.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Send a new gas coin from the simulator's own accounts
+    Fund {
+        to: String,
+        amount: u64,
+    },
+    /// Transfer IOTA from a scenario account
+    Transfer {
+        from: String,
+        to: String,
+        amount: u64,
+    },
+    /// Transfers from the simulator's own accounts to random addresses
+    RandomTransfers(u32),
+    /// Build and publish a Move package from a scenario account
+    Publish {
+        name: String,
+        path: PathBuf,
+        sender: String,
+        #[serde(default)]
+        named_addresses: BTreeMap<String, String>,
+    },
+    /// Call a Move function from a scenario account
+    Call {
+        sender: String,
+        package: String,
+        module: String,
+        function: String,
+        #[serde(default)]
+        type_arguments: Vec<String>,
+        #[serde(default)]
+        arguments: Vec<CallArgument>,
+    },
+    Checkpoints(u32),
+    AdvanceClock {
+        duration_ms: u64,
+    },
+    AdvanceEpoch,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CallArgument {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    Address(String),
+    String(String),
+    VectorU8(Vec<u8>),
+    /// An object ID, passed by reference or, for shared objects, mutably
+    Object(String),
+}
+
+impl Scenario {
+    /// Parses a scenario and the line on which each of its steps starts. As JSON is a
+    /// subset of YAML, both are accepted.
+    pub fn from_file(path: &Path) -> anyhow::Result<(Self, Vec<usize>)> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read scenario {}", path.display()))?;
+        let scenario: Scenario = serde_yaml::from_str(&source)
+            .map_err(|err| anyhow!("{}: invalid scenario: {err}", path.display()))?;
+        let step_lines = step_lines(&source);
+
+        Ok((scenario, step_lines))
+    }
+}
+
+/// Executes the scenario in `path` and creates a final checkpoint, so that all of its
+/// transactions are indexed.
+pub fn run_scenario(sim: &mut Simulacrum, rng: &mut StdRng, path: &Path) -> anyhow::Result<()> {
+    let (scenario, step_lines) = Scenario::from_file(path)?;
+    let mut runner = ScenarioRunner {
+        sim,
+        rng,
+        scenario_dir: path.parent().unwrap_or(Path::new("")),
+        accounts: BTreeMap::new(),
+        packages: BTreeMap::new(),
+    };
+
+    for (name, spec) in &scenario.accounts {
+        runner
+            .create_account(name, spec)
+            .with_context(|| format!("{}: account `{name}` failed", path.display()))?;
+    }
+
+    for (index, step) in scenario.steps.iter().enumerate() {
+        runner.run_step(step).with_context(|| {
+            let location = match step_lines.get(index) {
+                Some(line) => format!("{}:{line}", path.display()),
+                None => path.display().to_string(),
+            };
+            format!("{location}: step {} failed", index + 1)
+        })?;
+    }
+
+    runner.sim.create_checkpoint();
+    Ok(())
+}
+
+struct ScenarioRunner<'a> {
+    sim: &'a mut Simulacrum,
+    rng: &'a mut StdRng,
+    /// Relative package paths are resolved against it, not the working directory
+    scenario_dir: &'a Path,
+    accounts: BTreeMap<String, (IotaAddress, IotaKeyPair)>,
+    packages: BTreeMap<String, ObjectID>,
+}
+
+impl ScenarioRunner<'_> {
+    fn create_account(&mut self, name: &str, spec: &AccountSpec) -> anyhow::Result<()> {
+        let (address, keypair): (_, AccountKeyPair) = get_key_pair_from_rng(self.rng);
+        if spec.balance > 0 {
+            self.sim.request_gas(address, spec.balance)?;
+        }
+
+        println!("Scenario account {name}: {address}");
+        self.accounts
+            .insert(name.to_string(), (address, IotaKeyPair::Ed25519(keypair)));
+        Ok(())
+    }
+
+    fn run_step(&mut self, step: &Step) -> anyhow::Result<()> {
+        match step {
+            Step::Fund { to, amount } => {
+                let recipient = self.resolve_address(to)?;
+                self.sim.request_gas(recipient, *amount)?;
+            }
+            Step::Transfer { from, to, amount } => {
+                let recipient = self.resolve_address(to)?;
+                let mut builder = ProgrammableTransactionBuilder::new();
+                builder.transfer_iota(recipient, Some(*amount));
+                self.execute_as(from, builder.finish())?;
+            }
+            Step::RandomTransfers(count) => {
+                execute_simulacrum_transactions(self.sim, self.rng, *count);
+            }
+            Step::Publish {
+                name,
+                path,
+                sender,
+                named_addresses,
+            } => {
+                let named_addresses = named_addresses
+                    .iter()
+                    .map(|(name, value)| {
+                        Ok((
+                            name.clone(),
+                            AccountAddress::from(self.resolve_address(value)?),
+                        ))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                let (sender, keypair) = self
+                    .account(sender)
+                    .map(|(address, keypair)| (*address, keypair.copy()))?;
+                let path = self.scenario_dir.join(path);
+                let package = publish_package(self.sim, &path, named_addresses, sender, &keypair)?;
+
+                println!("Scenario package {name}: {}", package.package_id);
+                self.packages.insert(name.clone(), package.package_id);
+            }
+            Step::Call {
+                sender,
+                package,
+                module,
+                function,
+                type_arguments,
+                arguments,
+            } => {
+                let package = self.resolve_package(package)?;
+                let type_arguments = type_arguments
+                    .iter()
+                    .map(|type_tag| parse_iota_type_tag(type_tag))
+                    .collect::<anyhow::Result<_>>()?;
+
+                let mut builder = ProgrammableTransactionBuilder::new();
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.call_argument(&mut builder, argument))
+                    .collect::<anyhow::Result<_>>()?;
+                builder.programmable_move_call(
+                    package,
+                    Identifier::new(module.as_str())?,
+                    Identifier::new(function.as_str())?,
+                    type_arguments,
+                    arguments,
+                );
+                self.execute_as(sender, builder.finish())?;
+            }
+            Step::Checkpoints(count) => add_checkpoints(self.sim, *count),
+            Step::AdvanceClock { duration_ms } => {
+                self.sim.advance_clock(Duration::from_millis(*duration_ms));
+            }
+            Step::AdvanceEpoch => {
+                self.sim.advance_epoch();
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_as(
+        &mut self,
+        account: &str,
+        pt: ProgrammableTransaction,
+    ) -> anyhow::Result<TransactionEffects> {
+        let (sender, keypair) = self
+            .account(account)
+            .map(|(address, keypair)| (*address, keypair.copy()))?;
+        let tx_data = build_programmable_transaction(self.sim, sender, pt)?;
+
+        sign_and_execute(self.sim, tx_data, &keypair)
+    }
+
+    fn call_argument(
+        &self,
+        builder: &mut ProgrammableTransactionBuilder,
+        argument: &CallArgument,
+    ) -> anyhow::Result<Argument> {
+        match argument {
+            CallArgument::Bool(v) => builder.pure(*v),
+            CallArgument::U8(v) => builder.pure(*v),
+            CallArgument::U16(v) => builder.pure(*v),
+            CallArgument::U32(v) => builder.pure(*v),
+            CallArgument::U64(v) => builder.pure(*v),
+            CallArgument::U128(v) => builder.pure(*v),
+            CallArgument::Address(v) => builder.pure(self.resolve_address(v)?),
+            CallArgument::String(v) => builder.pure(v.as_str()),
+            CallArgument::VectorU8(v) => builder.pure(v.clone()),
+            CallArgument::Object(id) => {
+                let id = ObjectID::from_str(id)?;
+                let object = self
+                    .sim
+                    .get_object(&id)?
+                    .ok_or_else(|| anyhow!("object {id} does not exist"))?;
+                let object_arg = match object.owner {
+                    Owner::Shared {
+                        initial_shared_version,
+                    } => ObjectArg::SharedObject {
+                        id,
+                        initial_shared_version,
+                        mutable: true,
+                    },
+                    _ => ObjectArg::ImmOrOwnedObject(object.compute_object_reference()),
+                };
+                builder.obj(object_arg)
+            }
+        }
+    }
+
+    fn account(&self, name: &str) -> anyhow::Result<&(IotaAddress, IotaKeyPair)> {
+        self.accounts
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown account `{name}`"))
+    }
+
+    fn resolve_address(&self, value: &str) -> anyhow::Result<IotaAddress> {
+        if let Some((address, _)) = self.accounts.get(value) {
+            return Ok(*address);
+        }
+        if let Some(package_id) = self.packages.get(value) {
+            return Ok(IotaAddress::from(*package_id));
+        }
+        IotaAddress::from_str(value)
+            .map_err(|_| anyhow!("`{value}` is neither a known account nor an address"))
+    }
+
+    fn resolve_package(&self, value: &str) -> anyhow::Result<ObjectID> {
+        if let Some(package_id) = self.packages.get(value) {
+            return Ok(*package_id);
+        }
+        ObjectID::from_str(value)
+            .map_err(|_| anyhow!("`{value}` is neither a published package nor a package ID"))
+    }
+}
+
+/// Finds the line on which each entry of the top-level `steps` sequence starts.
+fn step_lines(source: &str) -> Vec<usize> {
+    let mut locator = StepLocator::default();
+    // Syntax errors are reported by serde_yaml, this only adds line information
+    _ = Parser::new_from_str(source).load(&mut locator, false);
+    locator.lines
+}
+
+#[derive(Default)]
+struct StepLocator {
+    depth: usize,
+    /// Whether the next node in the top-level mapping is a value rather than a key
+    expecting_value: bool,
+    last_key_is_steps: bool,
+    steps_depth: Option<usize>,
+    lines: Vec<usize>,
+}
+
+impl StepLocator {
+    fn finish_top_level_node(&mut self) {
+        self.expecting_value = !self.expecting_value;
+    }
+}
+
+impl MarkedEventReceiver for StepLocator {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let starts_node = matches!(
+            event,
+            Event::Scalar(..)
+                | Event::Alias(..)
+                | Event::SequenceStart(..)
+                | Event::MappingStart(..)
+        );
+        if starts_node && self.steps_depth == Some(self.depth) {
+            self.lines.push(mark.line());
+        }
+
+        match event {
+            Event::SequenceStart(..) => {
+                if self.depth == 1 && self.expecting_value && self.last_key_is_steps {
+                    self.steps_depth = Some(2);
+                }
+                self.depth += 1;
+            }
+            Event::MappingStart(..) => self.depth += 1,
+            Event::SequenceEnd | Event::MappingEnd => {
+                if self.steps_depth == Some(self.depth) {
+                    self.steps_depth = None;
+                }
+                self.depth -= 1;
+                if self.depth == 1 {
+                    self.finish_top_level_node();
+                }
+            }
+            Event::Scalar(value, ..) if self.depth == 1 => {
+                if !self.expecting_value {
+                    self.last_key_is_steps = value == "steps";
+                }
+                self.finish_top_level_node();
+            }
+            Event::Alias(..) if self.depth == 1 => self.finish_top_level_node(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_lines_in_yaml() {
+        let source = "\
+accounts:
+  alice:
+    balance: 1000
+steps:
+  - transfer: { from: alice, to: bob, amount: 1 }
+  - advance_epoch
+
+  - call:
+      package: counter
+      steps: [1, 2]
+";
+        assert_eq!(step_lines(source), vec![5, 6, 8]);
+    }
+
+    #[test]
+    fn step_lines_in_json() {
+        let source = r#"{
+  "accounts": { "alice": { "balance": 1000 } },
+  "steps": [
+    { "transfer": { "from": "alice", "to": "bob", "amount": 1 } },
+    "advance_epoch",
+    { "checkpoints": 10 }
+  ]
+}"#;
+        assert_eq!(step_lines(source), vec![4, 5, 6]);
+    }
+}
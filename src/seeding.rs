@@ -0,0 +1,95 @@
+use crate::config::SeedingConfig;
+use crate::simulacrum::Simulacrum;
+use iota_types::base_types::IotaAddress;
+use rand::rngs::StdRng;
+
+/// Fills a fresh Simulacrum with transfers and checkpoints spread over several epochs.
+pub fn seed_simulacrum(sim: &mut Simulacrum, rng: &mut StdRng, seeding: &SeedingConfig) {
+    for (epoch, transactions_count) in seeding.transactions_per_epoch.iter().enumerate() {
+        if epoch > 0 {
+            sim.advance_epoch();
+        }
+
+        execute_simulacrum_transactions(sim, rng, *transactions_count);
+        add_checkpoints(sim, seeding.checkpoints_per_epoch);
+    }
+}
+
+fn execute_simulacrum_transaction(sim: &mut Simulacrum, rng: &mut StdRng) {
+    let transfer_recipient = IotaAddress::generate(rng);
+    let (transaction, _) = sim.transfer_txn(transfer_recipient);
+    sim.execute_transaction(transaction.clone()).unwrap();
+}
+
+pub(crate) fn execute_simulacrum_transactions(
+    sim: &mut Simulacrum,
+    rng: &mut StdRng,
+    transactions_count: u32,
+) {
+    for _ in 0..transactions_count {
+        execute_simulacrum_transaction(sim, rng);
+    }
+}
+
+pub(crate) fn add_checkpoints(sim: &mut Simulacrum, checkpoints_count: u32) {
+    // Main use of this function is to create more checkpoints than the current
+    // processing batch size, to circumvent the issue described in
+    // https://github.com/iotaledger/iota/issues/2197#issuecomment-2376432709
+    for _ in 0..checkpoints_count {
+        sim.create_checkpoint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DevAccountsConfig;
+    use crate::dev_accounts::DevAccounts;
+    use crate::simulacrum::{highest_checkpoint, new_simulacrum};
+    use iota_types::digests::CheckpointDigest;
+
+    /// The checkpoint digests and dev account addresses of a seeded chain.
+    fn build_chain(seed: u64) -> (Vec<CheckpointDigest>, Vec<IotaAddress>) {
+        let (mut sim, mut rng) = new_simulacrum(Some(seed));
+        let seeding = SeedingConfig {
+            transactions_per_epoch: vec![3, 2],
+            checkpoints_per_epoch: 2,
+        };
+        seed_simulacrum(&mut sim, &mut rng, &seeding);
+        let dev_accounts_config = DevAccountsConfig {
+            count: 2,
+            ..DevAccountsConfig::default()
+        };
+        let dev_accounts = DevAccounts::derive(&dev_accounts_config, &mut rng).unwrap();
+
+        let checkpoint_digests = (0..=highest_checkpoint(&sim).unwrap())
+            .map(|sequence_number| {
+                *sim.store()
+                    .get_checkpoint_by_sequence_number(sequence_number)
+                    .unwrap()
+                    .digest()
+            })
+            .collect();
+        let addresses = dev_accounts
+            .accounts
+            .iter()
+            .map(|account| account.address)
+            .collect();
+        (checkpoint_digests, addresses)
+    }
+
+    #[test]
+    fn same_seed_builds_the_same_chain() {
+        let (checkpoint_digests, addresses) = build_chain(42);
+        assert!(checkpoint_digests.len() > 1);
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(
+            build_chain(42),
+            (checkpoint_digests.clone(), addresses.clone())
+        );
+
+        let (other_digests, other_addresses) = build_chain(43);
+        assert_ne!(other_digests[0], checkpoint_digests[0]);
+        assert_ne!(other_addresses, addresses);
+    }
+}
@@ -1,15 +1,42 @@
-use crate::consts::get_control_binding_ip;
-use axum::extract::State;
-use axum::http::StatusCode;
+use crate::archive::Archive;
+use crate::block_production::{BlockProducer, BlockProductionMode};
+use crate::dev_accounts::{DevAccounts, DevAccountsInfo};
+use crate::executor::{execute_transaction, ExecutionOptions};
+use crate::faucet_limits::FaucetLimiter;
+use crate::move_package::{build_move_package, publish_transaction, PublishedPackage};
+use crate::simulacrum::{highest_checkpoint, IndexerWriter, Simulacrum};
+use crate::state_overrides::{self, WrittenObject};
+use crate::transactions::build_programmable_transaction;
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use iota_types::base_types::{IotaAddress, ObjectID};
 use iota_types::crypto::AuthorityStrongQuorumSignInfo;
+use iota_types::effects::TransactionEffectsAPI;
+use iota_types::execution_status::ExecutionStatus;
 use iota_types::messages_checkpoint::CheckpointSummary;
+use iota_types::object::Owner;
+use iota_types::quorum_driver_types::{ExecuteTransactionRequestV1, ExecuteTransactionResponseV1};
+use iota_types::transaction::{Transaction, TransactionData, TransactionDataAPI};
+use iota_types::utils::to_sender_signed_transaction;
 use serde::{Deserialize, Serialize};
-use simulacrum::Simulacrum;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use axum::response::IntoResponse;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Clone)]
+pub struct ControlApiState {
+    pub sim: Arc<RwLock<Simulacrum>>,
+    pub block_producer: Arc<BlockProducer>,
+    pub faucet_limiter: Arc<FaucetLimiter>,
+    pub dev_accounts: Arc<DevAccounts>,
+    pub indexer_writer: Option<Arc<IndexerWriter>>,
+}
 
 async fn health() -> &'static str {
     "OK"
@@ -22,10 +49,10 @@ pub struct Checkpoint {
 }
 
 async fn create_checkpoint(
-    State(state): State<Arc<RwLock<Simulacrum>>>, // Use the trait object
+    State(state): State<ControlApiState>,
 ) -> Result<Json<Checkpoint>, StatusCode> {
-    let mut s = state.write().unwrap();
-    let c = s.create_checkpoint().clone();
+    let mut s = state.sim.write().unwrap();
+    let c = state.block_producer.create_checkpoint(&mut s);
 
     Ok(Json(Checkpoint {
         authority: c.auth_sig().clone(),
@@ -39,21 +66,23 @@ pub struct AdvanceClockRequest {
 }
 
 async fn advance_clock(
-    State(state): State<Arc<RwLock<Simulacrum>>>,
+    State(state): State<ControlApiState>,
     Json(payload): Json<AdvanceClockRequest>,
 ) -> impl IntoResponse {
-    let mut s = state.write().unwrap();
+    let mut s = state.sim.write().unwrap();
     s.advance_clock(Duration::from_millis(payload.duration as u64));
     ()
 }
 
-async fn advance_epoch(State(state): State<Arc<RwLock<Simulacrum>>>) -> impl IntoResponse {
-    let mut s = state.write().unwrap();
+async fn advance_epoch(State(state): State<ControlApiState>) -> impl IntoResponse {
+    let mut s = state.sim.write().unwrap();
     s.advance_epoch()
 }
 
-async fn get_checkpoint(State(state): State<Arc<RwLock<Simulacrum>>>) -> Result<Json<Checkpoint>, StatusCode> {
-    let mut s = state.write().unwrap();
+async fn get_checkpoint(
+    State(state): State<ControlApiState>,
+) -> Result<Json<Checkpoint>, StatusCode> {
+    let mut s = state.sim.write().unwrap();
     let checkpoint = s.store().get_highest_checkpoint().clone().unwrap();
     Ok(Json(Checkpoint {
         authority: checkpoint.auth_sig().clone(),
@@ -61,18 +90,392 @@ async fn get_checkpoint(State(state): State<Arc<RwLock<Simulacrum>>>) -> Result<
     }))
 }
 
-pub async fn start_control_api(sim: Arc<RwLock<Simulacrum>>) -> std::io::Result<()> {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockProductionStatus {
+    #[serde(flatten)]
+    pub mode: BlockProductionMode,
+    pub pending_transactions: u64,
+}
+
+async fn get_block_production(State(state): State<ControlApiState>) -> Json<BlockProductionStatus> {
+    Json(BlockProductionStatus {
+        mode: state.block_producer.mode(),
+        pending_transactions: state.sim.read().unwrap().pending_transactions(),
+    })
+}
+
+async fn set_block_production(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<BlockProductionMode>,
+) -> Result<Json<BlockProductionStatus>, (StatusCode, String)> {
+    payload
+        .validate()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    println!("Switching block production to {payload}");
+    state.block_producer.set_mode(payload);
+
+    Ok(get_block_production(State(state)).await)
+}
+
+/// The dev accounts including their private keys, like `anvil` prints them.
+async fn get_accounts(State(state): State<ControlApiState>) -> Json<DevAccountsInfo> {
+    Json(state.dev_accounts.info())
+}
+
+/// An unsigned transaction, either as base64 encoded BCS bytes or as JSON.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ExecuteRequest {
+    pub sender: IotaAddress,
+    #[serde(default)]
+    pub tx_bytes: Option<String>,
+    #[serde(default)]
+    pub transaction_data: Option<TransactionData>,
+    #[serde(default)]
+    pub checkpoint: bool,
+}
+
+impl ExecuteRequest {
+    fn transaction_data(self) -> Result<TransactionData, String> {
+        let tx_data = match (self.tx_bytes, self.transaction_data) {
+            (Some(tx_bytes), None) => {
+                let bytes = BASE64_STANDARD
+                    .decode(tx_bytes)
+                    .map_err(|err| format!("invalid base64 in tx_bytes: {err}"))?;
+                bcs::from_bytes::<TransactionData>(&bytes)
+                    .map_err(|err| format!("invalid transaction data: {err}"))?
+            }
+            (None, Some(tx_data)) => tx_data,
+            _ => return Err("expected either tx_bytes or transaction_data".to_string()),
+        };
+
+        if tx_data.sender() != self.sender {
+            return Err(format!(
+                "transaction is sent by {}, not by {}",
+                tx_data.sender(),
+                self.sender
+            ));
+        }
+        Ok(tx_data)
+    }
+}
+
+/// Signs the transaction with the key of a dev account and executes it like the REST
+/// API would.
+async fn execute(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<ExecuteRequest>,
+) -> Result<Json<ExecuteTransactionResponseV1>, (StatusCode, String)> {
+    let account = state.dev_accounts.get(&payload.sender).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("{} is not a dev account", payload.sender),
+        )
+    })?;
+    let options = ExecutionOptions {
+        force_checkpoint: payload.checkpoint,
+        impersonate: false,
+    };
+    let tx_data = payload
+        .transaction_data()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let transaction = to_sender_signed_transaction(tx_data, &account.keypair);
+    execute_with_options(&state, transaction, options)
+}
+
+/// Executes the transaction as its sender without a signature, e.g. to call admin-gated
+/// functions owned by addresses whose keys are unknown.
+async fn impersonate(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<ExecuteRequest>,
+) -> Result<Json<ExecuteTransactionResponseV1>, (StatusCode, String)> {
+    let options = ExecutionOptions {
+        force_checkpoint: payload.checkpoint,
+        impersonate: true,
+    };
+    let tx_data = payload
+        .transaction_data()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let transaction = Transaction::from_data(tx_data, Vec::new());
+    execute_with_options(&state, transaction, options)
+}
+
+fn execute_with_options(
+    state: &ControlApiState,
+    transaction: Transaction,
+    options: ExecutionOptions,
+) -> Result<Json<ExecuteTransactionResponseV1>, (StatusCode, String)> {
+    let mut s = state.sim.write().unwrap();
+    execute_locked(&mut s, state, transaction, options).map(Json)
+}
+
+fn execute_locked(
+    s: &mut Simulacrum,
+    state: &ControlApiState,
+    transaction: Transaction,
+    options: ExecutionOptions,
+) -> Result<ExecuteTransactionResponseV1, (StatusCode, String)> {
+    let mut request = ExecuteTransactionRequestV1::new(transaction);
+    request.include_input_objects = true;
+    request.include_output_objects = true;
+
+    execute_transaction(s, &state.block_producer, request, options)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PublishRequest {
+    /// Directory of the Move package, as seen by the simulator
+    pub path: PathBuf,
+    /// The dev account that publishes and receives the `UpgradeCap`, the first one by
+    /// default
+    #[serde(default)]
+    pub sender: Option<IotaAddress>,
+    #[serde(default)]
+    pub named_addresses: BTreeMap<String, IotaAddress>,
+    #[serde(default)]
+    pub checkpoint: bool,
+}
+
+/// Builds the Move package in `path` and publishes it from a dev account.
+async fn publish(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<PublishRequest>,
+) -> Result<Json<PublishedPackage>, (StatusCode, String)> {
+    let account = match payload.sender {
+        Some(sender) => state.dev_accounts.get(&sender),
+        None => state.dev_accounts.accounts.first(),
+    }
+    .ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            match payload.sender {
+                Some(sender) => format!("{sender} is not a dev account"),
+                None => "there are no dev accounts to publish from".to_string(),
+            },
+        )
+    })?;
+
+    let path = payload.path;
+    let named_addresses = payload
+        .named_addresses
+        .into_iter()
+        .map(|(name, address)| (name, address.into()))
+        .collect();
+    let package = tokio::task::spawn_blocking(move || build_move_package(&path, named_addresses))
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .map_err(bad_request)?;
+
+    // Gas is selected and spent under one lock, so that no other request can take it
+    let mut s = state.sim.write().unwrap();
+    let pt = publish_transaction(package, account.address);
+    let tx_data = build_programmable_transaction(&s, account.address, pt).map_err(bad_request)?;
+    let transaction = to_sender_signed_transaction(tx_data, &account.keypair);
+    let options = ExecutionOptions {
+        force_checkpoint: payload.checkpoint,
+        impersonate: false,
+    };
+    let response = execute_locked(&mut s, &state, transaction, options)?;
+
+    let effects = &response.effects.effects;
+    if let ExecutionStatus::Failure { error, .. } = effects.status() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "transaction {} failed: {error:?}",
+                effects.transaction_digest()
+            ),
+        ));
+    }
+    PublishedPackage::from_effects(&s, effects)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MintCoinRequest {
+    pub owner: IotaAddress,
+    /// The `T` of `Coin<T>`
+    #[serde(default = "default_coin_type")]
+    pub coin_type: String,
+    pub amount: u64,
+}
+
+fn default_coin_type() -> String {
+    "0x2::iota::IOTA".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SetObjectContentsRequest {
+    pub object_id: ObjectID,
+    /// Base64 encoded BCS bytes of the Move struct
+    pub contents: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SetOwnerRequest {
+    pub object_id: ObjectID,
+    pub owner: Owner,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteObjectRequest {
+    pub object_id: ObjectID,
+}
+
+async fn mint_coin(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<MintCoinRequest>,
+) -> Result<Json<WrittenObject>, (StatusCode, String)> {
+    let mut s = state.sim.write().unwrap();
+    state_overrides::mint_coin(&mut s, payload.owner, &payload.coin_type, payload.amount)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn set_object_contents(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<SetObjectContentsRequest>,
+) -> Result<Json<WrittenObject>, (StatusCode, String)> {
+    let contents = BASE64_STANDARD
+        .decode(payload.contents)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid base64: {err}")))?;
+
+    let mut s = state.sim.write().unwrap();
+    state_overrides::set_object_contents(&mut s, payload.object_id, contents)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn set_owner(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<SetOwnerRequest>,
+) -> Result<Json<WrittenObject>, (StatusCode, String)> {
+    let mut s = state.sim.write().unwrap();
+    state_overrides::set_owner(&mut s, payload.object_id, payload.owner)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn delete_object(
+    State(state): State<ControlApiState>,
+    Json(payload): Json<DeleteObjectRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut s = state.sim.write().unwrap();
+    state_overrides::delete_object(&mut s, payload.object_id).map_err(bad_request)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn bad_request(err: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+
+/// The chain as an archive file, which can be booted with `--load-archive`.
+async fn archive(State(state): State<ControlApiState>) -> Result<Response, (StatusCode, String)> {
+    let bytes = {
+        let s = state.sim.read().unwrap();
+        Archive::from_simulacrum(&s, &state.dev_accounts).and_then(|archive| archive.to_bytes())
+    }
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes).into_response())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub id: u64,
+}
+
+/// Remembers the current state, like `evm_snapshot`.
+async fn snapshot(State(state): State<ControlApiState>) -> Json<Snapshot> {
+    let mut s = state.sim.write().unwrap();
+    Json(Snapshot { id: s.snapshot() })
+}
+
+/// Rebuilds the chain as it was at snapshot `id`, like `evm_revert`, and reindexes it.
+/// Returns once the indexer, if enabled, caught up with the reverted chain.
+async fn revert(
+    State(state): State<ControlApiState>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if !state.sim.read().unwrap().has_snapshot(id) {
+        return Err((StatusCode::NOT_FOUND, format!("unknown snapshot {id}")));
+    }
+
+    let sim = Arc::clone(&state.sim);
+    // Only spawned once the indexer writer stopped reading the checkpoint files
+    let revert_chain = move || {
+        tokio::task::spawn_blocking(move || {
+            let mut s = sim.write().unwrap();
+            s.revert(id)?;
+            anyhow::Ok(highest_checkpoint(&s))
+        })
+    };
+    let reverted_checkpoint = match &state.indexer_writer {
+        Some(indexer_writer) => indexer_writer.reindex(revert_chain).await,
+        None => revert_chain().await,
+    }
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))?;
+    println!("Reverted to snapshot {id}");
+
+    if let (Some(indexer_writer), Some(sequence_number)) =
+        (&state.indexer_writer, reverted_checkpoint)
+    {
+        if !indexer_writer.wait_for_checkpoint(sequence_number).await {
+            return Err((
+                StatusCode::GATEWAY_TIMEOUT,
+                format!("the indexer did not catch up with checkpoint {sequence_number} in time"),
+            ));
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Lets the next faucet requests start from fresh rate limits and quotas.
+async fn reset_faucet_limits(State(state): State<ControlApiState>) -> impl IntoResponse {
+    state.faucet_limiter.reset();
+    StatusCode::NO_CONTENT
+}
+
+pub async fn start_control_api(
+    state: ControlApiState,
+    listener: tokio::net::TcpListener,
+    cancellation_token: CancellationToken,
+) -> std::io::Result<()> {
     let app = Router::new()
         .route("/", get(health))
         .route("/checkpoint", get(get_checkpoint))
         .route("/create_checkpoint", post(create_checkpoint))
         .route("/advance_clock", post(advance_clock))
         .route("/advance_epoch", post(advance_epoch))
-        .with_state(sim);
+        .route(
+            "/block_production",
+            get(get_block_production).post(set_block_production),
+        )
+        .route("/faucet/reset_limits", post(reset_faucet_limits))
+        .route("/accounts", get(get_accounts))
+        .route("/execute", post(execute))
+        .route("/impersonate", post(impersonate))
+        .route("/publish", post(publish))
+        .route("/state/mint_coin", post(mint_coin))
+        .route("/state/set_object_contents", post(set_object_contents))
+        .route("/state/set_owner", post(set_owner))
+        .route("/state/delete_object", post(delete_object))
+        .route("/archive", get(archive))
+        .route("/snapshot", post(snapshot))
+        .route("/revert/{id}", post(revert))
+        .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind(get_control_binding_ip())
+    axum::serve(listener, app)
+        .with_graceful_shutdown(cancellation_token.cancelled_owned())
         .await
-        .unwrap();
-
-    axum::serve(listener, app).await
 }
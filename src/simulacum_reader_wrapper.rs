@@ -1,3 +1,4 @@
+use crate::simulacrum::Simulacrum;
 use iota_types::base_types::{EpochId, IotaAddress, ObjectID, TransactionDigest, VersionNumber};
 use iota_types::committee::Committee;
 use iota_types::digests::{
@@ -14,7 +15,6 @@ use iota_types::storage::{
 };
 use iota_types::transaction::VerifiedTransaction;
 use move_core_types::language_storage::StructTag;
-use simulacrum::Simulacrum;
 use std::sync::{Arc, RwLock};
 pub struct SimulacrumReaderWrapper {
     pub inner: Arc<RwLock<Simulacrum>>,
@@ -0,0 +1,518 @@
+use crate::archive::Archive;
+use crate::block_production::{BlockProducer, BlockProductionMode};
+use crate::config::{
+    DatabaseMode, DevAccountsConfig, Endpoints, FaucetPolicy, ForkConfig, PublishConfig,
+    SeedingConfig, ServiceConfig, SimulatorConfig,
+};
+use crate::consts::{get_client_ip, SERVICE_START_ATTEMPTS, SERVICE_START_TIMEOUT};
+use crate::dev_accounts::DevAccounts;
+use crate::fork::fork;
+use crate::managed_postgres::ManagedPostgres;
+use crate::move_package::publish_package;
+use crate::scenario::run_scenario;
+use crate::seeding::seed_simulacrum;
+use crate::simulacrum::Simulacrum;
+use crate::simulacrum::{
+    highest_checkpoint, new_simulacrum, start_simulacrum_rest_api_with_read_write_indexer,
+    ServiceListeners, SimulacrumServices,
+};
+use crate::state_dir::{PersistedChain, StateDir, StateMetadata};
+use anyhow::{anyhow, bail, Context};
+use iota_indexer::store::PgIndexerStore;
+use jsonrpsee::http_client::HttpClient;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tempfile::{tempdir, TempDir};
+use tokio::net::TcpStream;
+
+/// Configures and starts a simulator from within an async context.
+///
+/// ```ignore
+/// let sim = SimulatorBuilder::new()
+///     .ephemeral_ports(true)
+///     .database_name("my_test_db")
+///     .start()
+///     .await?;
+///
+/// let rpc_url = sim.rpc_url();
+/// sim.shutdown().await;
+/// ```
+///
+/// Several simulators can run in the same process as long as they use distinct ports
+/// and database names.
+#[derive(Default)]
+pub struct SimulatorBuilder {
+    config: SimulatorConfig,
+}
+
+impl SimulatorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: SimulatorConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn bind_address(mut self, bind_address: IpAddr) -> Self {
+        self.config.bind_address = bind_address;
+        self
+    }
+
+    pub fn indexer_port(mut self, port: u16) -> Self {
+        self.config.indexer.port = port;
+        self
+    }
+
+    pub fn rpc_port(mut self, port: u16) -> Self {
+        self.config.rpc.port = port;
+        self
+    }
+
+    pub fn faucet_port(mut self, port: u16) -> Self {
+        self.config.faucet.port = port;
+        self
+    }
+
+    pub fn control_port(mut self, port: u16) -> Self {
+        self.config.control.port = port;
+        self
+    }
+
+    pub fn ephemeral_ports(mut self, ephemeral_ports: bool) -> Self {
+        self.config.ephemeral_ports = ephemeral_ports;
+        self
+    }
+
+    pub fn database_url(mut self, url: impl Into<String>) -> Self {
+        self.config.database.url = url.into();
+        self
+    }
+
+    pub fn database_name(mut self, name: impl Into<String>) -> Self {
+        self.config.database.name = name.into();
+        self
+    }
+
+    pub fn database_mode(mut self, mode: DatabaseMode) -> Self {
+        self.config.database.mode = mode;
+        self
+    }
+
+    pub fn postgres_bin_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.database.postgres_bin_dir = Some(path.into());
+        self
+    }
+
+    pub fn block_production(mut self, mode: BlockProductionMode) -> Self {
+        self.config.block_production = mode;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    pub fn faucet_policy(mut self, policy: FaucetPolicy) -> Self {
+        self.config.faucet_policy = policy;
+        self
+    }
+
+    pub fn dev_accounts(mut self, dev_accounts: DevAccountsConfig) -> Self {
+        self.config.dev_accounts = dev_accounts;
+        self
+    }
+
+    pub fn publish(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.publish.push(PublishConfig {
+            path: path.into(),
+            named_addresses: Default::default(),
+        });
+        self
+    }
+
+    pub fn fork(mut self, fork: ForkConfig) -> Self {
+        self.config.fork = fork;
+        self
+    }
+
+    pub fn seeding(mut self, seeding: SeedingConfig) -> Self {
+        self.config.seeding = seeding;
+        self
+    }
+
+    /// Persists the chain to `path` and resumes it from there if it was persisted before.
+    pub fn state_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.state_dir = Some(path.into());
+        self
+    }
+
+    pub fn scenario(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.scenario = Some(path.into());
+        self
+    }
+
+    /// Builds the chain like [`SimulatorBuilder::start`], but writes it to an archive
+    /// instead of starting any service.
+    pub fn dump(self, path: &Path) -> anyhow::Result<()> {
+        let config = self.config;
+        config.validate()?;
+
+        let persisted = match &config.state_dir {
+            Some(state_dir) => StateDir::open(state_dir)?.load()?,
+            None => None,
+        };
+        let (sim, dev_accounts) = build_chain(&config, persisted, None)?;
+        Archive::from_simulacrum(&sim, &dev_accounts)?.write(path)
+    }
+
+    pub async fn start(self) -> anyhow::Result<SimulatorHandle> {
+        let mut config = self.config;
+        config.validate()?;
+        if config.ephemeral_ports {
+            config.assign_ephemeral_ports()?;
+        }
+        let postgres = match config.database.mode {
+            DatabaseMode::Managed => {
+                let bin_dir = config.database.postgres_bin_dir.clone();
+                let postgres =
+                    tokio::task::spawn_blocking(move || ManagedPostgres::start(bin_dir.as_deref()))
+                        .await??;
+                config.database.url = postgres.url();
+                Some(postgres)
+            }
+            DatabaseMode::External | DatabaseMode::Disabled => None,
+        };
+        let mut listeners = Some(ServiceListeners::bind(&mut config).await?);
+
+        let state_dir = config
+            .state_dir
+            .as_deref()
+            .map(StateDir::open)
+            .transpose()?;
+        let (data_ingestion_dir, data_ingestion_path) = match &state_dir {
+            Some(state_dir) => (None, state_dir.checkpoints_path()),
+            None => {
+                let dir = tempdir()?;
+                let path = dir.path().to_path_buf();
+                (Some(dir), path)
+            }
+        };
+
+        let chain_config = config.clone();
+        let ingestion_path = data_ingestion_path.clone();
+        let (sim, dev_accounts) = tokio::task::spawn_blocking(move || {
+            let persisted = state_dir
+                .as_ref()
+                .map(StateDir::load)
+                .transpose()?
+                .flatten();
+            let stored_mnemonic = persisted
+                .as_ref()
+                .and_then(|chain| chain.metadata.dev_mnemonic.clone());
+            let (mut sim, dev_accounts) =
+                build_chain(&chain_config, persisted, Some(ingestion_path))?;
+
+            if let Some(state_dir) = &state_dir {
+                sim.persist_to(&state_dir.journal_path())?;
+                state_dir.write_metadata(
+                    &StateMetadata {
+                        seed: sim.seed(),
+                        // Kept when resuming without dev accounts, so later starts find them again
+                        dev_mnemonic: dev_accounts.mnemonic.clone().or(stored_mnemonic),
+                        dev_account_count: dev_accounts.accounts.len() as u32,
+                    },
+                    sim.network_config(),
+                )?;
+            }
+            anyhow::Ok((sim, dev_accounts))
+        })
+        .await??;
+        if let Some(keystore) = &config.dev_accounts.keystore {
+            dev_accounts.write_keystore(keystore)?;
+        }
+        let sim = Arc::new(RwLock::new(sim));
+        let dev_accounts = Arc::new(dev_accounts);
+
+        let mut attempt = 1;
+        let (services, client) = loop {
+            let listeners = match listeners.take() {
+                Some(listeners) => listeners,
+                None => ServiceListeners::bind(&mut config).await?,
+            };
+            let (services, client) = start_simulacrum_rest_api_with_read_write_indexer(
+                &config,
+                Arc::clone(&sim),
+                Arc::clone(&dev_accounts),
+                listeners,
+                data_ingestion_path.clone(),
+                Some(&config.database.name),
+            )
+            .await;
+            let Err(err) = wait_until_services_listen(&config, &services).await else {
+                break (services, client);
+            };
+
+            services.shutdown(&sim).await;
+            if !config.ephemeral_ports || attempt == SERVICE_START_ATTEMPTS {
+                return Err(err);
+            }
+            // The ports were released after looking them up, so another process may
+            // have taken one meanwhile
+            println!("{err:#}, retrying on other ports");
+            config.assign_ephemeral_ports()?;
+            attempt += 1;
+        };
+
+        Ok(SimulatorHandle {
+            config,
+            sim,
+            dev_accounts,
+            services,
+            client,
+            _data_ingestion_dir: data_ingestion_dir,
+            _postgres: postgres,
+        })
+    }
+}
+
+/// Waits for the services that bind their sockets themselves.
+async fn wait_until_services_listen(
+    config: &SimulatorConfig,
+    services: &SimulacrumServices,
+) -> anyhow::Result<()> {
+    wait_until_listening(config, "REST service", &config.rpc, || {
+        services.server_handle.is_finished()
+    })
+    .await?;
+    if let Some(reader_handle) = &services.indexer_reader_handle {
+        wait_until_listening(config, "indexer", &config.indexer, || {
+            reader_handle.is_finished()
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// The task of a service ends when it cannot bind its port, so a port accepting
+/// connections only counts while the task still runs.
+async fn wait_until_listening(
+    config: &SimulatorConfig,
+    name: &str,
+    service: &ServiceConfig,
+    is_finished: impl Fn() -> bool,
+) -> anyhow::Result<()> {
+    let address = SocketAddr::new(get_client_ip(config.bind_address(service)), service.port);
+    let connect = async {
+        loop {
+            let connected = TcpStream::connect(address).await.is_ok();
+            if is_finished() {
+                bail!("the {name} could not listen on {address}");
+            }
+            if connected {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(SERVICE_START_TIMEOUT, connect)
+        .await
+        .map_err(|_| anyhow!("the {name} did not listen on {address} in time"))?
+}
+
+fn build_chain(
+    config: &SimulatorConfig,
+    persisted: Option<PersistedChain>,
+    data_ingestion_path: Option<PathBuf>,
+) -> anyhow::Result<(Simulacrum, DevAccounts)> {
+    match (persisted, &config.load_archive) {
+        (Some(chain), archive) => {
+            if archive.is_some() {
+                // Restarting with the same flags has to resume, not load the archive again
+                println!("The state dir already holds a chain, not loading the archive");
+            }
+            resume_chain(config, chain, data_ingestion_path)
+        }
+        (None, Some(path)) => {
+            let archive = Archive::read(path)?;
+            let chain = archive
+                .chain()
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            let (sim, dev_accounts) = resume_chain(config, chain, data_ingestion_path)?;
+            archive
+                .verify(&sim)
+                .with_context(|| format!("failed to load {}", path.display()))?;
+            Ok((sim, dev_accounts))
+        }
+        (None, None) => create_chain(config, data_ingestion_path),
+    }
+}
+
+fn create_chain(
+    config: &SimulatorConfig,
+    data_ingestion_path: Option<PathBuf>,
+) -> anyhow::Result<(Simulacrum, DevAccounts)> {
+    let (mut sim, mut rng) = new_simulacrum(config.seed);
+    if let Some(path) = data_ingestion_path {
+        sim.set_data_ingestion_path(path);
+    }
+    if !config.fork.is_disabled() {
+        fork(&mut sim, &config.fork)?;
+    }
+    match &config.scenario {
+        Some(scenario) => run_scenario(&mut sim, &mut rng, scenario)?,
+        None => seed_simulacrum(&mut sim, &mut rng, &config.seeding),
+    }
+
+    let dev_accounts = DevAccounts::derive(&config.dev_accounts, &mut rng)?;
+    if !dev_accounts.accounts.is_empty() {
+        dev_accounts.fund(&mut sim, config.dev_accounts.balance)?;
+        for package in &config.publish {
+            let publisher = &dev_accounts.accounts[0];
+            let named_addresses = package
+                .named_addresses
+                .iter()
+                .map(|(name, address)| (name.clone(), (*address).into()))
+                .collect();
+            let published = publish_package(
+                &mut sim,
+                &package.path,
+                named_addresses,
+                publisher.address,
+                &publisher.keypair,
+            )?;
+            println!(
+                "Published {}: {}",
+                package.path.display(),
+                published.package_id
+            );
+        }
+        sim.create_checkpoint();
+    }
+    Ok((sim, dev_accounts))
+}
+
+/// The seeding, funding and publishing are part of the journal, so they are not repeated.
+fn resume_chain(
+    config: &SimulatorConfig,
+    chain: PersistedChain,
+    data_ingestion_path: Option<PathBuf>,
+) -> anyhow::Result<(Simulacrum, DevAccounts)> {
+    let PersistedChain {
+        metadata,
+        network_config,
+        journal,
+    } = chain;
+    if let Some(seed) = config.seed.filter(|seed| *seed != metadata.seed) {
+        bail!("the chain to resume has seed {}, not {seed}", metadata.seed);
+    }
+    if let (Some(stored), Some(configured)) =
+        (&metadata.dev_mnemonic, &config.dev_accounts.mnemonic)
+    {
+        if stored != configured {
+            bail!("the dev accounts of the chain to resume use a different mnemonic");
+        }
+    }
+
+    // The funding is part of the journal, so accounts the chain lacks cannot be added
+    let count = metadata.dev_account_count;
+    let configured = config.dev_accounts.count;
+    if configured != 0 && configured != count {
+        bail!("the chain to resume has {count} dev accounts, not {configured}");
+    }
+
+    let sim = Simulacrum::restore(metadata.seed, network_config, data_ingestion_path, &journal)?;
+    let dev_accounts_config = DevAccountsConfig {
+        count,
+        mnemonic: metadata
+            .dev_mnemonic
+            .or(config.dev_accounts.mnemonic.clone()),
+        ..config.dev_accounts.clone()
+    };
+    let dev_accounts = DevAccounts::derive(
+        &dev_accounts_config,
+        &mut StdRng::seed_from_u64(metadata.seed),
+    )?;
+
+    println!(
+        "Resumed chain with seed {} at checkpoint {}",
+        metadata.seed,
+        highest_checkpoint(&sim).unwrap_or_default()
+    );
+    Ok((sim, dev_accounts))
+}
+
+/// Dropping the handle leaves the services running until the runtime shuts down, but
+/// stops a managed Postgres server right away.
+pub struct SimulatorHandle {
+    config: SimulatorConfig,
+    sim: Arc<RwLock<Simulacrum>>,
+    dev_accounts: Arc<DevAccounts>,
+    services: SimulacrumServices,
+    client: HttpClient,
+    /// `None` with a state dir, which holds the checkpoint files instead
+    _data_ingestion_dir: Option<TempDir>,
+    /// Dropped last, once the indexer is stopped
+    _postgres: Option<ManagedPostgres>,
+}
+
+impl SimulatorHandle {
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        self.config.endpoints()
+    }
+
+    pub fn indexer_url(&self) -> Option<String> {
+        self.endpoints().indexer
+    }
+
+    pub fn rpc_url(&self) -> String {
+        self.config.client_url(&self.config.rpc)
+    }
+
+    pub fn faucet_url(&self) -> String {
+        self.config.client_url(&self.config.faucet)
+    }
+
+    pub fn control_url(&self) -> String {
+        self.config.client_url(&self.config.control)
+    }
+
+    pub fn sim(&self) -> &Arc<RwLock<Simulacrum>> {
+        &self.sim
+    }
+
+    pub fn dev_accounts(&self) -> &DevAccounts {
+        &self.dev_accounts
+    }
+
+    pub fn block_producer(&self) -> &Arc<BlockProducer> {
+        &self.services.block_producer
+    }
+
+    /// The store the indexer writes into. It is replaced when the chain is reverted.
+    pub async fn store(&self) -> Option<PgIndexerStore> {
+        match &self.services.indexer_writer {
+            Some(indexer_writer) => Some(indexer_writer.store().await),
+            None => None,
+        }
+    }
+
+    pub fn client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// Lets in-flight requests finish and the indexer catch up, then stops all services
+    /// and a managed Postgres server.
+    pub async fn shutdown(self) {
+        self.services.shutdown(&self.sim).await;
+    }
+}
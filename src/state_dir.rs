@@ -0,0 +1,99 @@
+use crate::journal::{read_journal, Operation};
+use anyhow::Context;
+use iota_swarm_config::network_config::NetworkConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const METADATA_FILE: &str = "simulator.json";
+const NETWORK_CONFIG_FILE: &str = "network.yaml";
+const JOURNAL_FILE: &str = "journal.bcs";
+const CHECKPOINTS_DIR: &str = "checkpoints";
+
+/// What is needed besides the network config and the journal to resume a chain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StateMetadata {
+    pub seed: u64,
+    /// The mnemonic the dev accounts were derived from
+    pub dev_mnemonic: Option<String>,
+    /// The number of funded dev accounts
+    pub dev_account_count: u32,
+}
+
+/// A chain to resume, from a state dir or an archive.
+pub struct PersistedChain {
+    pub metadata: StateMetadata,
+    pub network_config: Arc<NetworkConfig>,
+    pub journal: Vec<Operation>,
+}
+
+/// The directory given with `--state-dir`:
+///
+/// - `simulator.json`: the [`StateMetadata`]
+/// - `network.yaml`: genesis and the keys of the committee
+/// - `journal.bcs`: every change made to the chain since genesis
+/// - `checkpoints/`: the checkpoint files, used as the data ingestion directory
+pub struct StateDir {
+    path: PathBuf,
+}
+
+impl StateDir {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let state_dir = Self {
+            path: path.to_path_buf(),
+        };
+        std::fs::create_dir_all(state_dir.checkpoints_path())
+            .with_context(|| format!("failed to create state dir {}", path.display()))?;
+        Ok(state_dir)
+    }
+
+    pub fn checkpoints_path(&self) -> PathBuf {
+        self.path.join(CHECKPOINTS_DIR)
+    }
+
+    pub fn journal_path(&self) -> PathBuf {
+        self.path.join(JOURNAL_FILE)
+    }
+
+    /// The state of a previous run, or `None` if the directory is new.
+    pub fn load(&self) -> anyhow::Result<Option<PersistedChain>> {
+        let metadata_path = self.path.join(METADATA_FILE);
+        if !metadata_path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&metadata_path)
+            .with_context(|| format!("failed to read {}", metadata_path.display()))?;
+        let metadata = serde_json::from_str(&content)
+            .with_context(|| format!("invalid state metadata {}", metadata_path.display()))?;
+        let network_config_path = self.path.join(NETWORK_CONFIG_FILE);
+        let content = std::fs::read_to_string(&network_config_path)
+            .with_context(|| format!("failed to read {}", network_config_path.display()))?;
+        let network_config = serde_yaml::from_str(&content)
+            .with_context(|| format!("invalid network config {}", network_config_path.display()))?;
+        let journal = match self.journal_path().exists() {
+            true => read_journal(&self.journal_path())?,
+            false => Vec::new(),
+        };
+        Ok(Some(PersistedChain {
+            metadata,
+            network_config: Arc::new(network_config),
+            journal,
+        }))
+    }
+
+    /// Written before the metadata, whose presence marks the directory as holding a chain.
+    pub fn write_metadata(
+        &self,
+        metadata: &StateMetadata,
+        network_config: &NetworkConfig,
+    ) -> anyhow::Result<()> {
+        let network_config_path = self.path.join(NETWORK_CONFIG_FILE);
+        std::fs::write(&network_config_path, serde_yaml::to_string(network_config)?)
+            .with_context(|| format!("failed to write {}", network_config_path.display()))?;
+
+        let metadata_path = self.path.join(METADATA_FILE);
+        std::fs::write(&metadata_path, serde_json::to_string_pretty(metadata)?)
+            .with_context(|| format!("failed to write {}", metadata_path.display()))
+    }
+}
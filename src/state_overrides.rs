@@ -0,0 +1,114 @@
+use crate::simulacrum::Simulacrum;
+use anyhow::{anyhow, bail};
+use iota_types::base_types::{IotaAddress, ObjectID, ObjectRef, SequenceNumber};
+use iota_types::digests::{ObjectDigest, TransactionDigest};
+use iota_types::object::{MoveObject, Object, Owner};
+use iota_types::parse_iota_type_tag;
+use iota_types::storage::ObjectStore;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The reference of an object after it was written.
+#[derive(Serialize, Debug, Clone)]
+pub struct WrittenObject {
+    pub object_id: ObjectID,
+    pub version: SequenceNumber,
+    pub digest: ObjectDigest,
+}
+
+impl From<ObjectRef> for WrittenObject {
+    fn from((object_id, version, digest): ObjectRef) -> Self {
+        Self {
+            object_id,
+            version,
+            digest,
+        }
+    }
+}
+
+// These functions write straight into the store, without a transaction. The written
+// objects are visible through the REST API right away, but the indexer only learns
+// about them once a transaction touches them.
+
+/// Creates a `Coin<coin_type>` of `amount` owned by `owner`.
+pub fn mint_coin(
+    sim: &mut Simulacrum,
+    owner: IotaAddress,
+    coin_type: &str,
+    amount: u64,
+) -> anyhow::Result<WrittenObject> {
+    let coin_type = parse_iota_type_tag(coin_type)?;
+    let coin = MoveObject::new_coin(
+        coin_type,
+        SequenceNumber::from_u64(1),
+        ObjectID::random(),
+        amount,
+    );
+    let object = Object::new_move(
+        coin,
+        Owner::AddressOwner(owner),
+        TransactionDigest::genesis_marker(),
+    );
+
+    write_object(sim, object)
+}
+
+/// Replaces the BCS encoded contents of a Move object.
+pub fn set_object_contents(
+    sim: &mut Simulacrum,
+    object_id: ObjectID,
+    contents: Vec<u8>,
+) -> anyhow::Result<WrittenObject> {
+    let mut object = live_object(sim, object_id)?;
+    let Some(move_object) = object.data.try_as_move_mut() else {
+        bail!("{object_id} is a package, not a Move object");
+    };
+    move_object.update_contents(contents)?;
+
+    write_object(sim, next_version(object))
+}
+
+pub fn set_owner(
+    sim: &mut Simulacrum,
+    object_id: ObjectID,
+    owner: Owner,
+) -> anyhow::Result<WrittenObject> {
+    let mut object = live_object(sim, object_id)?;
+    object.owner = owner;
+
+    write_object(sim, next_version(object))
+}
+
+pub fn delete_object(sim: &mut Simulacrum, object_id: ObjectID) -> anyhow::Result<()> {
+    let object = live_object(sim, object_id)?;
+    sim.update_objects(
+        BTreeMap::new(),
+        vec![(
+            object_id,
+            object.version(),
+            ObjectDigest::OBJECT_DIGEST_DELETED,
+        )],
+    );
+    Ok(())
+}
+
+fn live_object(sim: &Simulacrum, object_id: ObjectID) -> anyhow::Result<Object> {
+    sim.get_object(&object_id)?
+        .ok_or_else(|| anyhow!("object {object_id} does not exist"))
+}
+
+/// Bumps the version, so that references to the previous contents become stale like
+/// they would after a transaction.
+fn next_version(mut object: Object) -> Object {
+    if let Some(move_object) = object.data.try_as_move_mut() {
+        let version = SequenceNumber::from_u64(move_object.version().value() + 1);
+        move_object.increment_version_to(version);
+    }
+    object
+}
+
+fn write_object(sim: &mut Simulacrum, object: Object) -> anyhow::Result<WrittenObject> {
+    let object_ref = object.compute_object_reference();
+    sim.update_objects(BTreeMap::from([(object.id(), object)]), Vec::new());
+    Ok(object_ref.into())
+}
@@ -0,0 +1,58 @@
+use crate::simulacrum::Simulacrum;
+use anyhow::{anyhow, bail};
+use iota_types::base_types::{IotaAddress, ObjectRef};
+use iota_types::crypto::IotaKeyPair;
+use iota_types::effects::{TransactionEffects, TransactionEffectsAPI};
+use iota_types::transaction::{ProgrammableTransaction, TransactionData};
+use iota_types::utils::to_sender_signed_transaction;
+
+/// Upper limit of the gas budget for transactions built by the simulator itself
+pub const DEFAULT_GAS_BUDGET: u64 = 5_000_000_000;
+
+/// The gas coin with the highest balance owned by `owner`.
+pub fn select_gas_coin(sim: &Simulacrum, owner: IotaAddress) -> anyhow::Result<(ObjectRef, u64)> {
+    sim.store()
+        .owned_objects(owner)
+        .filter(|object| object.is_gas_coin())
+        .map(|object| {
+            (
+                object.compute_object_reference(),
+                object.get_coin_value_unsafe(),
+            )
+        })
+        .max_by_key(|(_, balance)| *balance)
+        .ok_or_else(|| anyhow!("{owner} does not own any gas coins"))
+}
+
+/// Wraps a programmable transaction, paying gas with the richest gas coin of `sender`.
+pub fn build_programmable_transaction(
+    sim: &Simulacrum,
+    sender: IotaAddress,
+    pt: ProgrammableTransaction,
+) -> anyhow::Result<TransactionData> {
+    let (gas_coin, balance) = select_gas_coin(sim, sender)?;
+
+    Ok(TransactionData::new_programmable(
+        sender,
+        vec![gas_coin],
+        pt,
+        DEFAULT_GAS_BUDGET.min(balance),
+        sim.reference_gas_price(),
+    ))
+}
+
+/// Signs and executes a transaction, failing if it could not be executed or its
+/// execution was aborted.
+pub fn sign_and_execute(
+    sim: &mut Simulacrum,
+    tx_data: TransactionData,
+    keypair: &IotaKeyPair,
+) -> anyhow::Result<TransactionEffects> {
+    let transaction = to_sender_signed_transaction(tx_data, keypair);
+    let (effects, execution_error) = sim.execute_transaction(transaction)?;
+
+    if let Some(err) = execution_error {
+        bail!("transaction {} failed: {err}", effects.transaction_digest());
+    }
+    Ok(effects)
+}